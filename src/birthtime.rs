@@ -0,0 +1,83 @@
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Returns when the file was created.
+///
+/// On Linux this asks `statx` for the birth time directly. The standard library
+/// only does that on glibc, so musl builds would otherwise never see it. Older
+/// kernels and filesystems such as some network mounts do not record a birth
+/// time, which is reported as `ErrorKind::Unsupported`.
+#[cfg(target_os = "linux")]
+pub fn birth_time(path: &Path) -> io::Result<SystemTime> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::time::Duration;
+
+    const STATX_BTIME: u32 = 0x0800;
+
+    #[repr(C)]
+    #[derive(Default)]
+    #[allow(dead_code)]
+    struct StatxTimestamp {
+        tv_sec: i64,
+        tv_nsec: u32,
+        _reserved: i32,
+    }
+
+    /// Layout of the kernel's `struct statx`, which is 256 bytes. Only the mask and
+    /// birth time are read back.
+    #[repr(C)]
+    #[derive(Default)]
+    #[allow(dead_code)]
+    struct Statx {
+        mask: u32,
+        blksize: u32,
+        attributes: u64,
+        nlink: u32,
+        uid: u32,
+        gid: u32,
+        mode: u16,
+        _spare0: u16,
+        ino: u64,
+        size: u64,
+        blocks: u64,
+        attributes_mask: u64,
+        atime: StatxTimestamp,
+        btime: StatxTimestamp,
+        ctime: StatxTimestamp,
+        mtime: StatxTimestamp,
+        _spare: [u64; 16],
+    }
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut buf = Statx::default();
+    // SAFETY: `c_path` is a valid NUL-terminated string and `buf` matches the
+    // kernel's statx layout, so the kernel writes within its bounds.
+    let result = unsafe {
+        libc::syscall(libc::SYS_statx, libc::AT_FDCWD, c_path.as_ptr(), 0, STATX_BTIME, &mut buf as *mut Statx)
+    };
+    if result != 0 {
+        let error = io::Error::last_os_error();
+        return Err(match error.raw_os_error() {
+            Some(libc::ENOSYS) => io::Error::new(io::ErrorKind::Unsupported, "statx is not supported by this kernel"),
+            _ => error,
+        });
+    }
+    if buf.mask & STATX_BTIME == 0 {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "birth time is not recorded by this filesystem"));
+    }
+
+    let seconds = Duration::from_secs(buf.btime.tv_sec.unsigned_abs());
+    let before_nanos = if buf.btime.tv_sec >= 0 {
+        SystemTime::UNIX_EPOCH + seconds
+    } else {
+        SystemTime::UNIX_EPOCH - seconds
+    };
+    Ok(before_nanos + Duration::from_nanos(buf.btime.tv_nsec as u64))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn birth_time(path: &Path) -> io::Result<SystemTime> {
+    std::fs::metadata(path)?.created()
+}
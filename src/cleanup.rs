@@ -0,0 +1,141 @@
+use crate::junk::{JunkFilter, JunkPolicy};
+use crate::report::Report;
+use log::{debug, info, warn};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// A report line for a folder (or junk inside it) deleted from the source.
+#[derive(Debug, Serialize)]
+pub struct DeletedRecord<'a> {
+    pub action: &'static str,
+    pub source: &'a Path,
+}
+
+/// Deletes source folders left empty by sorting.
+///
+/// Only folders that held a file processed by this run are considered, along with
+/// their parents once those become empty in turn. Folders that were already empty,
+/// such as a camera's `DCIM/100CANON` skeleton, are left alone, as are protected
+/// folders and the source itself.
+#[derive(Debug)]
+pub struct FolderCleanup {
+    /// Absolute paths, or bare folder names matched anywhere in the source.
+    protected: Vec<PathBuf>,
+    junk: JunkFilter,
+    /// Whether folders holding only junk files count as empty, deleting the junk.
+    junk_is_empty: bool,
+    candidates: Mutex<HashSet<PathBuf>>,
+}
+
+impl FolderCleanup {
+    /// `protected` entries containing a path separator are taken relative to the
+    /// source; other entries match folders with that name at any depth.
+    pub fn new(source: &Path, protected: &[PathBuf], junk: JunkFilter, junk_is_empty: bool) -> Self {
+        let protected = protected
+            .iter()
+            .map(|entry| {
+                if entry.components().count() > 1 || entry.is_absolute() {
+                    absolute(&source.join(entry))
+                } else {
+                    entry.clone()
+                }
+            })
+            .collect();
+        FolderCleanup { protected, junk, junk_is_empty, candidates: Mutex::new(HashSet::new()) }
+    }
+
+    /// Records that a file in this folder was sorted, making the folder a candidate.
+    pub fn note_processed(&self, file_path: &Path) {
+        if let Some(parent) = file_path.parent() {
+            self.candidates.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(absolute(parent));
+        }
+    }
+
+    /// Deletes candidate folders that are now empty, walking up towards the source.
+    pub fn run(&self, source: &Path, report: Option<&Report>) {
+        let source = absolute(source);
+        let mut candidates = self.candidates.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut finished = Vec::new();
+        for candidate in candidates.iter() {
+            let mut dir = candidate.as_path();
+            while dir != source && dir.starts_with(&source) {
+                if !dir.exists() {
+                    finished.push(candidate.clone());
+                    break;
+                }
+                if self.is_protected(dir) {
+                    debug!("Keeping protected folder {}", dir.display());
+                    break;
+                }
+                match self.remove_if_empty(dir, report) {
+                    Ok(true) => {
+                        finished.push(candidate.clone());
+                        dir = match dir.parent() {
+                            Some(parent) => parent,
+                            None => break,
+                        };
+                    }
+                    Ok(false) => break,
+                    Err(e) => {
+                        warn!("Failed to delete folder {}: {}. It might already be deleted or access is denied.", dir.display(), e);
+                        break;
+                    }
+                }
+            }
+        }
+        for candidate in finished {
+            candidates.remove(&candidate);
+        }
+    }
+
+    fn is_protected(&self, dir: &Path) -> bool {
+        self.protected.iter().any(|protected| {
+            if protected.is_absolute() {
+                dir == protected
+            } else {
+                dir.file_name() == Some(protected.as_os_str())
+            }
+        })
+    }
+
+    /// Deletes `dir` if it is empty, or only holds junk files when those count as
+    /// empty. Junk kept by the `leave` policy and junk folders such as `.Trashes`
+    /// always keep the folder. Returns whether it was deleted.
+    fn remove_if_empty(&self, dir: &Path, report: Option<&Report>) -> io::Result<bool> {
+        let mut junk = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let is_junk = self.junk_is_empty
+                && entry.file_type()?.is_file()
+                && matches!(
+                    self.junk.policy_for(&entry.file_name().to_string_lossy()),
+                    Some(JunkPolicy::Ignore | JunkPolicy::Delete)
+                );
+            if !is_junk {
+                return Ok(false);
+            }
+            junk.push(entry.path());
+        }
+        for file in &junk {
+            fs::remove_file(file)?;
+            info!(event = "deleted_junk", path:% = file.display(); "Deleting junk file: {}", file.display());
+            if let Some(report) = report {
+                report.record(&DeletedRecord { action: "deleted_junk", source: file });
+            }
+        }
+        fs::remove_dir(dir)?;
+        info!("Deleting empty folder: {}", dir.display());
+        if let Some(report) = report {
+            report.record(&DeletedRecord { action: "deleted_folder", source: dir });
+        }
+        Ok(true)
+    }
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
@@ -0,0 +1,189 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use log::{debug, info};
+use std::fs;
+use std::path::Path;
+
+/// A correction for one camera's clock, optionally limited to a range of camera time.
+#[derive(Debug)]
+struct ClockOffset {
+    camera: String,
+    offset: Duration,
+    from: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+}
+
+/// Clock corrections keyed by camera model or serial number.
+///
+/// The file has one entry per line: the camera model (as used in folder names, or
+/// with spaces) or body serial number, the offset to add, and an optional range of
+/// camera time the entry applies to. Blank lines and `#` comments are ignored.
+///
+/// ```text
+/// # camera        offset    from        until
+/// Canon_EOS_R6    -1h12m    2024-05-01  2024-05-14
+/// 012345678901    +3d4h     2024-06-01T12:00:00
+/// ```
+#[derive(Debug)]
+pub struct ClockOffsets {
+    entries: Vec<ClockOffset>,
+}
+
+impl ClockOffsets {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).with_context(|| format!("Failed to read clock offsets file {}", path.display()))?;
+        let mut entries = Vec::new();
+        for (line_no, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            let context = || format!("Invalid clock offset on line {} of {}", line_no + 1, path.display());
+            if columns.len() < 2 || columns.len() > 4 {
+                return Err(anyhow::anyhow!("Expected: <camera> <offset> [from] [until]")).with_context(context);
+            }
+            entries.push(ClockOffset {
+                camera: normalize_camera(columns[0]),
+                offset: parse_offset(columns[1]).with_context(context)?,
+                from: columns.get(2).map(|s| parse_time(s, false)).transpose().with_context(context)?,
+                until: columns.get(3).map(|s| parse_time(s, true)).transpose().with_context(context)?,
+            });
+        }
+        info!("Loaded {} clock offsets from {}", entries.len(), path.display());
+        Ok(ClockOffsets { entries })
+    }
+
+    /// Returns the offset for the first entry matching any of the camera identifiers
+    /// whose range contains the camera's own (uncorrected) time.
+    pub fn offset_for(&self, identifiers: &[String], camera_time: DateTime<Utc>) -> Option<Duration> {
+        let identifiers: Vec<String> = identifiers.iter().map(|id| normalize_camera(id)).collect();
+        let entry = self.entries.iter().find(|entry| {
+            identifiers.contains(&entry.camera)
+                && entry.from.is_none_or(|from| camera_time >= from)
+                && entry.until.is_none_or(|until| camera_time <= until)
+        })?;
+        debug!("Clock offset {} applies to camera {} at {}", format_offset(entry.offset), entry.camera, camera_time);
+        Some(entry.offset)
+    }
+}
+
+/// Camera names are compared case-insensitively with whitespace and underscores treated alike.
+fn normalize_camera(camera: &str) -> String {
+    camera.trim().to_lowercase().split(|c: char| c.is_whitespace() || c == '_').filter(|s| !s.is_empty()).collect::<Vec<_>>().join("_")
+}
+
+/// Parses offsets like `+1h12m`, `-30s` or `2d3h4m5s`.
+pub fn parse_offset(value: &str) -> Result<Duration> {
+    let (sign, rest) = match value.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let mut total = Duration::zero();
+    let mut number = String::new();
+    for c in rest.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let amount: i64 = number.parse().with_context(|| format!("Missing number before '{}' in offset {}", c, value))?;
+        let part = match c {
+            'd' => Duration::try_days(amount),
+            'h' => Duration::try_hours(amount),
+            'm' => Duration::try_minutes(amount),
+            's' => Duration::try_seconds(amount),
+            _ => anyhow::bail!("Unknown unit '{}' in offset {} (use d, h, m or s)", c, value),
+        };
+        total = part
+            .and_then(|part| total.checked_add(&part))
+            .ok_or_else(|| anyhow::anyhow!("Offset {} is too large", value))?;
+        number.clear();
+    }
+    if !number.is_empty() || rest.is_empty() {
+        anyhow::bail!("Offset {} must end with a unit (d, h, m or s)", value);
+    }
+    Ok(total * sign)
+}
+
+/// Formats an offset in the same form `parse_offset` accepts.
+pub fn format_offset(offset: Duration) -> String {
+    let sign = if offset < Duration::zero() { '-' } else { '+' };
+    let mut seconds = offset.num_seconds().abs();
+    let mut formatted = String::from(sign);
+    for (unit, size) in [('d', 86400), ('h', 3600), ('m', 60)] {
+        if seconds >= size {
+            formatted.push_str(&format!("{}{}", seconds / size, unit));
+            seconds %= size;
+        }
+    }
+    if seconds > 0 || formatted.len() == 1 {
+        formatted.push_str(&format!("{}s", seconds));
+    }
+    formatted
+}
+
+/// Parses `YYYY-MM-DD` or `YYYY-MM-DDTHH:MM:SS`. A bare date used as the end of a
+/// range covers the whole day.
+fn parse_time(value: &str, end_of_day: bool) -> Result<DateTime<Utc>> {
+    if let Ok(naive) = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S") {
+        return Ok(Utc.from_utc_datetime(&naive));
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").with_context(|| format!("Invalid date {}", value))?;
+    let time = if end_of_day { date.and_hms_opt(23, 59, 59) } else { date.and_hms_opt(0, 0, 0) };
+    Ok(Utc.from_utc_datetime(&time.expect("valid time of day")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_offset_reads_units_and_signs() {
+        assert_eq!(parse_offset("+1h12m").unwrap(), Duration::minutes(72));
+        assert_eq!(parse_offset("-30s").unwrap(), Duration::seconds(-30));
+        assert_eq!(parse_offset("2d3h4m5s").unwrap(), Duration::seconds(2 * 86400 + 3 * 3600 + 4 * 60 + 5));
+        assert_eq!(parse_offset("90m").unwrap(), Duration::minutes(90));
+    }
+
+    #[test]
+    fn parse_offset_rejects_invalid_offsets() {
+        for value in ["", "+", "5", "1h5", "h", "1x", "1.5h", "999999999999999d", "99999999999999999999s", "9223372036854775807s"] {
+            assert!(parse_offset(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn format_offset_round_trips() {
+        for value in ["+0s", "+30s", "-30s", "+1h12m", "-1h12m", "+3d4h", "+2d3h4m5s", "-365d"] {
+            let offset = parse_offset(value).unwrap();
+            assert_eq!(format_offset(offset), value);
+            assert_eq!(parse_offset(&format_offset(offset)).unwrap(), offset);
+        }
+        assert_eq!(format_offset(parse_offset("90m").unwrap()), "+1h30m");
+    }
+
+    #[test]
+    fn load_reports_the_offending_line() {
+        let path = std::env::temp_dir().join(format!("shuttersort-clock-{}.txt", std::process::id()));
+        fs::write(&path, "# camera offset\nCanon_EOS_R6 -1h12m 2024-05-01 2024-05-14\nCam 999999999999999d\n").unwrap();
+        let error = format!("{:#}", ClockOffsets::load(&path).unwrap_err());
+        fs::remove_file(&path).unwrap();
+        assert!(error.contains("line 3"), "{}", error);
+    }
+
+    #[test]
+    fn offset_for_respects_camera_and_range() {
+        let offsets = ClockOffsets {
+            entries: vec![ClockOffset {
+                camera: normalize_camera("Canon EOS R6"),
+                offset: Duration::hours(-1),
+                from: Some(parse_time("2024-05-01", false).unwrap()),
+                until: Some(parse_time("2024-05-14", true).unwrap()),
+            }],
+        };
+        let camera = ["canon_eos_r6".to_string()];
+        assert_eq!(offsets.offset_for(&camera, parse_time("2024-05-14T23:00:00", false).unwrap()), Some(Duration::hours(-1)));
+        assert_eq!(offsets.offset_for(&camera, parse_time("2024-05-15", false).unwrap()), None);
+        assert_eq!(offsets.offset_for(&["Nikon".to_string()], parse_time("2024-05-02", false).unwrap()), None);
+    }
+}
@@ -0,0 +1,62 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Why a single file could not be sorted.
+#[derive(Debug)]
+pub enum SortError {
+    /// The file's metadata or name could not be turned into a destination.
+    Metadata { path: PathBuf, message: String },
+    /// Reading, writing or moving the file failed.
+    Io { path: PathBuf, source: io::Error },
+    /// The destination already holds a file with the chosen name.
+    Collision { path: PathBuf },
+    /// The operating system refused access to the file or a folder.
+    Permission { path: PathBuf, source: io::Error },
+}
+
+impl SortError {
+    pub fn metadata(path: &Path, message: impl fmt::Display) -> Self {
+        SortError::Metadata { path: path.to_path_buf(), message: message.to_string() }
+    }
+
+    /// Wraps an I/O error, telling permission problems apart from other failures.
+    pub fn io(path: &Path, source: io::Error) -> Self {
+        let path = path.to_path_buf();
+        match source.kind() {
+            io::ErrorKind::PermissionDenied => SortError::Permission { path, source },
+            io::ErrorKind::AlreadyExists => SortError::Collision { path },
+            _ => SortError::Io { path, source },
+        }
+    }
+
+    /// Short name of the failure category, used in logs and the report.
+    pub fn category(&self) -> &'static str {
+        match self {
+            SortError::Metadata { .. } => "metadata",
+            SortError::Io { .. } => "io",
+            SortError::Collision { .. } => "collision",
+            SortError::Permission { .. } => "permission",
+        }
+    }
+}
+
+impl fmt::Display for SortError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SortError::Metadata { path, message } => write!(f, "{}: {}", path.display(), message),
+            SortError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            SortError::Collision { path } => write!(f, "{} already exists", path.display()),
+            SortError::Permission { path, source } => write!(f, "{}: {}", path.display(), source),
+        }
+    }
+}
+
+impl std::error::Error for SortError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SortError::Io { source, .. } | SortError::Permission { source, .. } => Some(source),
+            SortError::Metadata { .. } | SortError::Collision { .. } => None,
+        }
+    }
+}
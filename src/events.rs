@@ -0,0 +1,89 @@
+use chrono::{DateTime, Datelike, Duration, Utc};
+use log::debug;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::geocode::haversine_km;
+
+/// Settings for grouping a batch of files into events by capture time.
+#[derive(Debug, Clone)]
+pub struct EventGrouping {
+    pub gap: Duration,
+    pub split_distance_km: Option<f64>,
+}
+
+/// The folder an event's files are sorted into, e.g. `2024/2024-05-03_to_05-05`.
+#[derive(Debug, Clone)]
+pub struct EventFolder {
+    pub year: i32,
+    pub name: String,
+}
+
+/// A file considered for event grouping.
+#[derive(Debug)]
+pub struct EventCandidate {
+    pub path: PathBuf,
+    pub date_time: DateTime<Utc>,
+    pub coordinates: Option<(f64, f64)>,
+}
+
+/// Sorts the candidates by capture time and starts a new event whenever the gap to the
+/// previous shot exceeds the threshold or, if enabled, the shot is too far from the last
+/// known position. Returns the event folder for every candidate.
+pub fn group_events(mut candidates: Vec<EventCandidate>, grouping: &EventGrouping) -> HashMap<PathBuf, EventFolder> {
+    candidates.sort_by_key(|candidate| candidate.date_time);
+
+    let mut events: Vec<Vec<EventCandidate>> = Vec::new();
+    let mut last_coordinates: Option<(f64, f64)> = None;
+    for candidate in candidates {
+        let starts_new_event = match events.last().and_then(|event| event.last()) {
+            None => true,
+            Some(previous) => {
+                let gap_exceeded = candidate.date_time - previous.date_time > grouping.gap;
+                let moved_too_far = match (grouping.split_distance_km, last_coordinates, candidate.coordinates) {
+                    (Some(limit), Some((lat1, lon1)), Some((lat2, lon2))) => haversine_km(lat1, lon1, lat2, lon2) > limit,
+                    _ => false,
+                };
+                if moved_too_far {
+                    debug!("Starting new event at {}: moved more than the split distance", candidate.path.display());
+                }
+                gap_exceeded || moved_too_far
+            }
+        };
+        if starts_new_event {
+            events.push(Vec::new());
+            last_coordinates = None;
+        }
+        if candidate.coordinates.is_some() {
+            last_coordinates = candidate.coordinates;
+        }
+        events.last_mut().unwrap().push(candidate);
+    }
+
+    let mut folders = HashMap::new();
+    for event in events {
+        let start = event[0].date_time;
+        let folder = EventFolder {
+            year: start.year(),
+            name: event_name(start, event[event.len() - 1].date_time),
+        };
+        debug!("Event {} contains {} files", folder.name, event.len());
+        for candidate in event {
+            folders.insert(candidate.path, folder.clone());
+        }
+    }
+    folders
+}
+
+/// Names an event after its first and last day, e.g. `2024-05-03`, `2024-05-03_to_05-05`
+/// or `2023-12-31_to_2024-01-01`.
+fn event_name(start: DateTime<Utc>, end: DateTime<Utc>) -> String {
+    let (start, end) = (start.date_naive(), end.date_naive());
+    if start == end {
+        start.format("%Y-%m-%d").to_string()
+    } else if start.year() == end.year() {
+        format!("{}_to_{}", start.format("%Y-%m-%d"), end.format("%m-%d"))
+    } else {
+        format!("{}_to_{}", start.format("%Y-%m-%d"), end.format("%Y-%m-%d"))
+    }
+}
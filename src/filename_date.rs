@@ -0,0 +1,171 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use log::debug;
+use regex::Regex;
+use std::path::Path;
+
+use crate::junk::relative_to;
+
+/// Patterns for names written by common phones, messengers and screenshot tools.
+/// Each uses the named groups `year`, `month` and `day`, and optionally `hour`,
+/// `minute` and `second`.
+const BUILTIN_FILE_PATTERNS: &[&str] = &[
+    // WhatsApp: IMG-20230514-WA0003.jpg, VID-20230514-WA0001.mp4
+    r"^(?:IMG|VID|AUD|PTT)-(?P<year>\d{4})(?P<month>\d{2})(?P<day>\d{2})-WA\d+",
+    // Pixel: PXL_20240101_123456789.jpg
+    r"^PXL_(?P<year>\d{4})(?P<month>\d{2})(?P<day>\d{2})_(?P<hour>\d{2})(?P<minute>\d{2})(?P<second>\d{2})",
+    // Android/Samsung camera: IMG_20240101_123456.jpg, VID_20240101_123456.mp4
+    r"^(?:IMG|VID|PANO|MVIMG|BURST|Screenrecorder)[_-](?P<year>\d{4})(?P<month>\d{2})(?P<day>\d{2})[_-](?P<hour>\d{2})(?P<minute>\d{2})(?P<second>\d{2})",
+    // Android screenshots: Screenshot_20240101-123456.png
+    r"^Screenshot_(?P<year>\d{4})(?P<month>\d{2})(?P<day>\d{2})-(?P<hour>\d{2})(?P<minute>\d{2})(?P<second>\d{2})",
+    // macOS and Android screenshots: Screenshot 2024-01-01 at 12.34.56.png, Screenshot_2024-01-01-12-34-56.png
+    r"^Screen ?[Ss]hot[ _-](?P<year>\d{4})-(?P<month>\d{2})-(?P<day>\d{2})(?:[ _-](?:at )?(?P<hour>\d{2})[.\-_](?P<minute>\d{2})[.\-_](?P<second>\d{2}))?",
+    // Generic timestamps: 2024-01-01 12.34.56, 2024-01-01_12-34-56, 20240101_123456
+    r"(?P<year>\d{4})-(?P<month>\d{2})-(?P<day>\d{2})[ _T](?P<hour>\d{2})[.\-:](?P<minute>\d{2})[.\-:](?P<second>\d{2})",
+    r"(?:^|[^\d])(?P<year>\d{4})(?P<month>\d{2})(?P<day>\d{2})[_-](?P<hour>\d{2})(?P<minute>\d{2})(?P<second>\d{2})(?:[^\d]|$)",
+    // Generic dates: 2024-01-01
+    r"(?:^|[^\d])(?P<year>\d{4})-(?P<month>\d{2})-(?P<day>\d{2})(?:[^\d]|$)",
+];
+
+/// Patterns for folder names such as `1998-07 Holiday` or `2003-12-24 Christmas`.
+const BUILTIN_FOLDER_PATTERNS: &[&str] = &[
+    r"^(?P<year>\d{4})[-_.](?P<month>\d{2})(?:[-_.](?P<day>\d{2}))?(?:[^\d]|$)",
+];
+
+/// Parses capture dates out of file and folder names.
+#[derive(Debug)]
+pub struct FilenameDateParser {
+    file_patterns: Vec<Regex>,
+    folder_patterns: Vec<Regex>,
+}
+
+impl FilenameDateParser {
+    /// Builds a parser from the built-in patterns, with user patterns tried first.
+    /// User patterns must contain `year` and `month` named groups and apply to
+    /// both file and folder names.
+    pub fn new(user_patterns: &[String]) -> Result<Self> {
+        let mut user_regexes = Vec::new();
+        for pattern in user_patterns {
+            let regex = Regex::new(pattern).with_context(|| format!("Invalid filename date pattern: {}", pattern))?;
+            let names: Vec<&str> = regex.capture_names().flatten().collect();
+            if !names.contains(&"year") || !names.contains(&"month") {
+                anyhow::bail!("Filename date pattern {} must contain (?P<year>...) and (?P<month>...) groups", pattern);
+            }
+            user_regexes.push(regex);
+        }
+
+        let builtin = |patterns: &[&str]| -> Vec<Regex> {
+            patterns.iter().map(|pattern| Regex::new(pattern).expect("built-in pattern is valid")).collect()
+        };
+        let mut file_patterns = user_regexes.clone();
+        file_patterns.extend(builtin(BUILTIN_FILE_PATTERNS));
+        let mut folder_patterns = user_regexes;
+        folder_patterns.extend(builtin(BUILTIN_FOLDER_PATTERNS));
+
+        Ok(FilenameDateParser { file_patterns, folder_patterns })
+    }
+
+    /// Looks for a date in the file name, then in each parent folder below `root`,
+    /// nearest first.
+    pub fn parse(&self, file_path: &Path, root: &Path) -> Result<DateTime<Utc>> {
+        if let Some(stem) = file_path.file_stem().and_then(|s| s.to_str())
+            && let Some(date_time) = match_patterns(&self.file_patterns, stem)
+        {
+            debug!("Found date {} in file name {}", date_time, file_path.display());
+            return Ok(date_time);
+        }
+
+        // File system events carry absolute paths even when the source was given as a relative one.
+        let relative = relative_to(file_path, root).unwrap_or_default();
+        for folder in relative.parent().into_iter().flat_map(Path::ancestors) {
+            if let Some(name) = folder.file_name().and_then(|s| s.to_str())
+                && let Some(date_time) = match_patterns(&self.folder_patterns, name)
+            {
+                debug!("Found date {} in folder name {}", date_time, folder.display());
+                return Ok(date_time);
+            }
+        }
+        anyhow::bail!("No date found in file or folder names for {}", file_path.display())
+    }
+}
+
+fn match_patterns(patterns: &[Regex], name: &str) -> Option<DateTime<Utc>> {
+    patterns.iter().find_map(|regex| {
+        let captures = regex.captures(name)?;
+        let group = |group_name: &str, default: u32| -> Option<u32> {
+            match captures.name(group_name) {
+                Some(m) => m.as_str().parse().ok(),
+                None => Some(default),
+            }
+        };
+        let year = captures.name("year")?.as_str().parse().ok()?;
+        Utc.with_ymd_and_hms(
+            year,
+            group("month", 1)?,
+            group("day", 1)?,
+            group("hour", 0)?,
+            group("minute", 0)?,
+            group("second", 0)?,
+        )
+        .single()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, mo: u32, d: u32, h: u32, mi: u32, s: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, mo, d, h, mi, s).unwrap()
+    }
+
+    fn parse(path: &str, root: &str) -> Option<DateTime<Utc>> {
+        FilenameDateParser::new(&[]).unwrap().parse(Path::new(path), Path::new(root)).ok()
+    }
+
+    #[test]
+    fn builtin_file_patterns() {
+        for (name, expected) in [
+            ("IMG-20230514-WA0003.jpg", date(2023, 5, 14, 0, 0, 0)),
+            ("VID-20230514-WA0001.mp4", date(2023, 5, 14, 0, 0, 0)),
+            ("PXL_20240101_123456789.jpg", date(2024, 1, 1, 12, 34, 56)),
+            ("IMG_20240101_123456.jpg", date(2024, 1, 1, 12, 34, 56)),
+            ("Screenshot_20240101-123456.png", date(2024, 1, 1, 12, 34, 56)),
+            ("Screenshot_2024-01-01-12-34-56.png", date(2024, 1, 1, 12, 34, 56)),
+            ("Screenshot 2024-01-01 at 12.34.56.png", date(2024, 1, 1, 12, 34, 56)),
+            ("2024-01-01 12.34.56.jpg", date(2024, 1, 1, 12, 34, 56)),
+            ("holiday_20240101_123456.jpg", date(2024, 1, 1, 12, 34, 56)),
+            ("scan 2024-01-01.jpg", date(2024, 1, 1, 0, 0, 0)),
+        ] {
+            assert_eq!(parse(&format!("src/{}", name), "src"), Some(expected), "{}", name);
+        }
+    }
+
+    #[test]
+    fn rejects_names_without_valid_dates() {
+        assert_eq!(parse("src/DSC_0001.jpg", "src"), None);
+        assert_eq!(parse("src/IMG-20231341-WA0003.jpg", "src"), None);
+        assert_eq!(parse("src/123420240101_1234567.jpg", "src"), None);
+    }
+
+    #[test]
+    fn folder_names_below_the_root() {
+        assert_eq!(parse("src/1998-07 Holiday/scan.jpg", "src"), Some(date(1998, 7, 1, 0, 0, 0)));
+        assert_eq!(parse("src/2003-12-24 Christmas/a/scan.jpg", "src"), Some(date(2003, 12, 24, 0, 0, 0)));
+        assert_eq!(parse("1998-07 Holiday/src/scan.jpg", "1998-07 Holiday/src"), None);
+    }
+
+    #[test]
+    fn folder_names_with_absolute_path_and_relative_root() {
+        let path = std::path::absolute("src/1998-07 Holiday/scan.jpg").unwrap();
+        assert_eq!(parse(path.to_str().unwrap(), "src"), Some(date(1998, 7, 1, 0, 0, 0)));
+    }
+
+    #[test]
+    fn user_patterns_need_year_and_month() {
+        assert!(FilenameDateParser::new(&[r"(?P<year>\d{4})".to_string()]).is_err());
+        assert!(FilenameDateParser::new(&["(".to_string()]).is_err());
+        let parser = FilenameDateParser::new(&[r"^scan(?P<year>\d{4})(?P<month>\d{2})".to_string()]).unwrap();
+        assert_eq!(parser.parse(Path::new("src/scan199807.jpg"), Path::new("src")).ok(), Some(date(1998, 7, 1, 0, 0, 0)));
+    }
+}
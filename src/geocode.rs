@@ -0,0 +1,158 @@
+use anyhow::{Context, Result};
+use log::{debug, info};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+const KM_PER_DEGREE: f64 = 111.32;
+
+/// A place resolved from GPS coordinates.
+#[derive(Debug, Clone, Serialize)]
+pub struct Location {
+    pub country: String,
+    pub region: Option<String>,
+    pub city: String,
+}
+
+#[derive(Debug)]
+struct Place {
+    name: String,
+    latitude: f64,
+    longitude: f64,
+    country_code: String,
+    admin1_code: String,
+}
+
+/// Offline reverse geocoder backed by GeoNames dump files.
+///
+/// Places are bucketed into one-degree cells so a lookup only has to look at
+/// the cells within `max_distance_km` of the coordinates.
+#[derive(Debug)]
+pub struct Geocoder {
+    places: Vec<Place>,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+    country_names: HashMap<String, String>,
+    admin1_names: HashMap<String, String>,
+    max_distance_km: f64,
+}
+
+impl Geocoder {
+    /// Loads a GeoNames cities file (e.g. `cities1000.txt`) and optionally the
+    /// `countryInfo.txt` and `admin1CodesASCII.txt` files for readable names.
+    /// Without them, folders use the ISO country code and the admin1 code.
+    pub fn load(cities: &Path, countries: Option<&Path>, admin1: Option<&Path>, max_distance_km: f64) -> Result<Self> {
+        let mut places = Vec::new();
+        let mut cells: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+
+        for (line_no, line) in read_lines(cities)?.enumerate() {
+            let line = line.with_context(|| format!("Failed to read {}", cities.display()))?;
+            let columns: Vec<&str> = line.split('\t').collect();
+            if columns.len() < 11 {
+                debug!("Skipping malformed GeoNames line {} in {}", line_no + 1, cities.display());
+                continue;
+            }
+            let (Ok(latitude), Ok(longitude)) = (columns[4].parse::<f64>(), columns[5].parse::<f64>()) else {
+                debug!("Skipping GeoNames line {} with invalid coordinates in {}", line_no + 1, cities.display());
+                continue;
+            };
+            cells.entry(cell(latitude, longitude)).or_default().push(places.len());
+            places.push(Place {
+                name: columns[1].to_string(),
+                latitude,
+                longitude,
+                country_code: columns[8].to_string(),
+                admin1_code: columns[10].to_string(),
+            });
+        }
+        if places.is_empty() {
+            anyhow::bail!("No places found in GeoNames file {}", cities.display());
+        }
+
+        let mut country_names = HashMap::new();
+        if let Some(path) = countries {
+            for line in read_lines(path)? {
+                let line = line.with_context(|| format!("Failed to read {}", path.display()))?;
+                if line.starts_with('#') {
+                    continue;
+                }
+                let columns: Vec<&str> = line.split('\t').collect();
+                if columns.len() > 4 {
+                    country_names.insert(columns[0].to_string(), columns[4].to_string());
+                }
+            }
+        }
+
+        let mut admin1_names = HashMap::new();
+        if let Some(path) = admin1 {
+            for line in read_lines(path)? {
+                let line = line.with_context(|| format!("Failed to read {}", path.display()))?;
+                let columns: Vec<&str> = line.split('\t').collect();
+                if columns.len() > 2 {
+                    admin1_names.insert(columns[0].to_string(), columns[2].to_string());
+                }
+            }
+        }
+
+        info!(
+            "Loaded {} places, {} countries and {} regions for reverse geocoding",
+            places.len(), country_names.len(), admin1_names.len()
+        );
+        Ok(Geocoder { places, cells, country_names, admin1_names, max_distance_km })
+    }
+
+    /// Finds the nearest known place to the coordinates, if one lies within the maximum distance.
+    pub fn lookup(&self, latitude: f64, longitude: f64) -> Option<Location> {
+        let lat_cells = (self.max_distance_km / KM_PER_DEGREE).ceil() as i32;
+        let lon_km_per_degree = KM_PER_DEGREE * latitude.to_radians().cos().max(0.01);
+        let lon_cells = ((self.max_distance_km / lon_km_per_degree).ceil() as i32).min(180);
+        let (lat_cell, lon_cell) = cell(latitude, longitude);
+
+        let mut nearest: Option<(f64, &Place)> = None;
+        for dlat in -lat_cells..=lat_cells {
+            for dlon in -lon_cells..=lon_cells {
+                let wrapped_lon = (lon_cell + dlon + 180).rem_euclid(360) - 180;
+                let Some(indices) = self.cells.get(&(lat_cell + dlat, wrapped_lon)) else {
+                    continue;
+                };
+                for &index in indices {
+                    let place = &self.places[index];
+                    let distance = haversine_km(latitude, longitude, place.latitude, place.longitude);
+                    if distance <= self.max_distance_km && nearest.is_none_or(|(best, _)| distance < best) {
+                        nearest = Some((distance, place));
+                    }
+                }
+            }
+        }
+
+        let (distance, place) = nearest?;
+        debug!("Nearest place to ({}, {}) is {} ({:.1} km)", latitude, longitude, place.name, distance);
+        let country = self.country_names.get(&place.country_code).cloned().unwrap_or_else(|| place.country_code.clone());
+        let region = if place.admin1_code.is_empty() {
+            None
+        } else {
+            let key = format!("{}.{}", place.country_code, place.admin1_code);
+            Some(self.admin1_names.get(&key).cloned().unwrap_or_else(|| place.admin1_code.clone()))
+        };
+        Some(Location { country, region, city: place.name.clone() })
+    }
+}
+
+fn read_lines(path: &Path) -> Result<impl Iterator<Item = std::io::Result<String>>> {
+    let file = File::open(path).with_context(|| format!("Failed to open GeoNames file {}", path.display()))?;
+    Ok(BufReader::new(file).lines())
+}
+
+fn cell(latitude: f64, longitude: f64) -> (i32, i32) {
+    (latitude.floor() as i32, longitude.floor() as i32)
+}
+
+/// Great-circle distance between two coordinates in kilometres.
+pub fn haversine_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let dlat = (lat2 - lat1).to_radians();
+    let dlon = (lon2 - lon1).to_radians();
+    let a = (dlat / 2.0).sin().powi(2) + lat1.to_radians().cos() * lat2.to_radians().cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().min(1.0).asin()
+}
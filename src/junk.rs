@@ -0,0 +1,102 @@
+use clap::ValueEnum;
+use std::path::{Component, Path, PathBuf};
+
+/// Files and folders operating systems leave behind, matched case-insensitively.
+/// `*` matches any run of characters.
+const BUILTIN_JUNK: &[&str] = &[
+    ".DS_Store",
+    "._*",
+    ".Trashes",
+    ".Spotlight-V100",
+    ".fseventsd",
+    ".TemporaryItems",
+    ".localized",
+    "Thumbs.db",
+    "ehthumbs.db",
+    "desktop.ini",
+    "$RECYCLE.BIN",
+    "System Volume Information",
+];
+
+/// What to do with junk found in the source.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JunkPolicy {
+    /// Do not sort it and leave it in place. With `--junk-is-empty`, junk files do
+    /// not stop their folder counting as empty and are deleted along with it.
+    Ignore,
+    /// Delete it from the source when it is found (not in copy mode).
+    Delete,
+    /// Do not sort it and leave it in place, always keeping its folder.
+    Leave,
+}
+
+/// The built-in junk list and any extra patterns, each with its own policy.
+#[derive(Debug, Clone)]
+pub struct JunkFilter {
+    lists: Vec<(Vec<String>, JunkPolicy)>,
+}
+
+impl JunkFilter {
+    pub fn new(builtin_policy: JunkPolicy, extra_patterns: &[String], extra_policy: JunkPolicy) -> Self {
+        let builtin = BUILTIN_JUNK.iter().map(|pattern| pattern.to_string()).collect();
+        // Extra patterns come first, so they can give a built-in name another policy.
+        JunkFilter { lists: vec![(extra_patterns.to_vec(), extra_policy), (builtin, builtin_policy)] }
+    }
+
+    /// The policy for a file or folder name, if it is junk.
+    pub fn policy_for(&self, name: &str) -> Option<JunkPolicy> {
+        self.lists
+            .iter()
+            .find(|(patterns, _)| patterns.iter().any(|pattern| glob_match(pattern, name)))
+            .map(|(_, policy)| *policy)
+    }
+
+    /// Whether a path below `source` is a junk folder such as `.Trashes`, or lies
+    /// inside one. Junk folders are never scanned. Only the part of the path below
+    /// the source is checked, so a source that itself sits in such a folder works.
+    pub fn excludes(&self, path: &Path, source: &Path) -> bool {
+        let Some(relative) = relative_to(path, source) else {
+            return false;
+        };
+        let mut components: Vec<_> = relative.components().collect();
+        if !path.is_dir() {
+            components.pop();
+        }
+        components.iter().any(|component| match component {
+            Component::Normal(name) => self.policy_for(&name.to_string_lossy()).is_some(),
+            _ => false,
+        })
+    }
+}
+
+/// `path` relative to `base`, comparing absolute paths when only one of them is.
+pub fn relative_to(path: &Path, base: &Path) -> Option<PathBuf> {
+    if let Ok(relative) = path.strip_prefix(base) {
+        return Some(relative.to_path_buf());
+    }
+    let path = std::path::absolute(path).ok()?;
+    let base = std::path::absolute(base).ok()?;
+    path.strip_prefix(base).ok().map(Path::to_path_buf)
+}
+
+/// Case-insensitive match where `*` stands for any run of characters.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let name = name.to_lowercase();
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
@@ -0,0 +1,159 @@
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+/// Name of the lock file created in locked directories. Files with this name are
+/// never sorted.
+pub const LOCK_FILE_NAME: &str = ".shuttersort.lock";
+
+/// An advisory lock stopping two ShutterSort processes from writing into the same
+/// directory.
+///
+/// The lock file is held with an exclusive file lock for as long as the instance
+/// runs, so a crashed owner releases it automatically and two instances can never
+/// both take over a stale lock. The file records the owner as `PID@host` and is
+/// emptied, not deleted, when dropped, so every instance locks the same file.
+#[derive(Debug)]
+pub struct InstanceLock {
+    path: PathBuf,
+    file: File,
+}
+
+/// Who wrote a lock file.
+#[derive(Debug, PartialEq, Eq)]
+struct Owner {
+    pid: u32,
+    host: String,
+}
+
+impl Owner {
+    fn current() -> Self {
+        Owner { pid: std::process::id(), host: hostname() }
+    }
+
+    fn parse(content: &str) -> Option<Self> {
+        let (pid, host) = content.trim().split_once('@')?;
+        Some(Owner { pid: pid.parse().ok()?, host: host.to_string() })
+    }
+}
+
+impl std::fmt::Display for Owner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}@{}", self.pid, self.host)
+    }
+}
+
+impl InstanceLock {
+    /// Takes the lock for `dir`. A lock left behind by a process on this host that
+    /// is no longer running is taken over; one held by a live process, or written
+    /// by another host sharing the directory, is an error.
+    pub fn acquire(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create directory {}", dir.display()))?;
+        let path = dir.join(LOCK_FILE_NAME);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("Failed to open lock file {}", path.display()))?;
+        let mut content = String::new();
+        file.read_to_string(&mut content).with_context(|| format!("Failed to read lock file {}", path.display()))?;
+        let previous = Owner::parse(&content);
+        let current = Owner::current();
+
+        let held_elsewhere = |owner: &Option<Owner>| match owner {
+            Some(owner) => anyhow::anyhow!(
+                "Another ShutterSort instance ({}) is using {}. If it is not running, delete {}.",
+                owner,
+                dir.display(),
+                path.display()
+            ),
+            None => anyhow::anyhow!("Another ShutterSort instance is using {}", dir.display()),
+        };
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Err(held_elsewhere(&previous)),
+            Err(TryLockError::Error(e)) => {
+                // Some network filesystems do not support locks. Fall back to the PID.
+                debug!("Cannot lock {} ({}). Checking the recorded owner instead.", path.display(), e);
+                if let Some(owner) = &previous
+                    && owner.host == current.host
+                    && owner.pid != current.pid
+                    && process_alive(owner.pid)
+                {
+                    return Err(held_elsewhere(&previous));
+                }
+            }
+        }
+        // File locks are not always shared between hosts on network filesystems, so
+        // a record from another host is never broken automatically.
+        if let Some(owner) = &previous
+            && owner.host != current.host
+        {
+            anyhow::bail!(
+                "{} is locked by ShutterSort on host {} (PID {}). If it is not running, delete {}.",
+                dir.display(),
+                owner.host,
+                owner.pid,
+                path.display()
+            );
+        }
+        if !content.trim().is_empty() {
+            warn!("Taking over stale lock file {} (owner: {})", path.display(), previous.as_ref().map_or("unknown".to_string(), ToString::to_string));
+        }
+
+        file.set_len(0)?;
+        file.rewind()?;
+        writeln!(file, "{}", current).with_context(|| format!("Failed to write lock file {}", path.display()))?;
+        debug!("Acquired lock {}", path.display());
+        Ok(InstanceLock { path, file })
+    }
+}
+
+impl Drop for InstanceLock {
+    /// Empties the lock file, marking the directory as free. The file lock itself is
+    /// released when the file is closed.
+    fn drop(&mut self) {
+        if let Err(e) = self.file.set_len(0) {
+            warn!("Failed to clear lock file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: `buf` is valid for writes of its full length.
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return "unknown".to_string();
+    }
+    let len = buf.iter().position(|&byte| byte == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+#[cfg(not(unix))]
+fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| "unknown".to_string())
+}
+
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: signal 0 only checks whether the process exists and may be signalled.
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return true;
+    }
+    // EPERM means the process exists but belongs to another user.
+    io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Without a portable way to check, a lock whose PID cannot be verified is kept.
+#[cfg(not(unix))]
+fn process_alive(_pid: u32) -> bool {
+    true
+}
@@ -0,0 +1,151 @@
+use crate::progress;
+use anyhow::{Context, Result};
+use chrono::{SecondsFormat, Utc};
+use clap::ValueEnum;
+use log::LevelFilter;
+use log::kv::{self, Key, VisitSource};
+use serde_json::{Map, Value};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+/// How log lines are written.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines.
+    Text,
+    /// One JSON object per line, with the event's fields as keys.
+    Json,
+}
+
+/// Where and how to write logs, from the global command-line options.
+pub struct LogSettings<'a> {
+    pub verbose: bool,
+    pub format: LogFormat,
+    pub file: Option<&'a Path>,
+    pub max_file_size: u64,
+    pub keep_files: u32,
+}
+
+/// Sets up the global logger. `RUST_LOG` still overrides the level.
+pub fn init(settings: &LogSettings) -> Result<()> {
+    let default_level = if settings.verbose { LevelFilter::Debug } else { LevelFilter::Info };
+    let mut builder = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default_level.as_str()));
+    if settings.format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let mut line = Map::new();
+            line.insert("timestamp".into(), Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true).into());
+            line.insert("level".into(), record.level().as_str().into());
+            line.insert("target".into(), record.target().into());
+            line.insert("message".into(), record.args().to_string().into());
+            let _ = record.key_values().visit(&mut JsonFields(&mut line));
+            writeln!(buf, "{}", Value::Object(line))
+        });
+    }
+    if let Some(path) = settings.file {
+        let file = RotatingFile::open(path, settings.max_file_size, settings.keep_files)?;
+        builder.target(env_logger::Target::Pipe(Box::new(file)));
+        builder.write_style(env_logger::WriteStyle::Never);
+    } else if io::stderr().is_terminal() {
+        // Written through a pipe so a progress bar can be hidden around each line.
+        builder.target(env_logger::Target::Pipe(Box::new(ProgressAwareStderr)));
+        if std::env::var_os("RUST_LOG_STYLE").is_none() {
+            builder.write_style(env_logger::WriteStyle::Always);
+        }
+    }
+    builder.try_init().context("Failed to set up logging")
+}
+
+/// Stderr, with any progress bar hidden while a log line is written.
+struct ProgressAwareStderr;
+
+impl Write for ProgressAwareStderr {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        progress::suspend(|| io::stderr().write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+/// Copies a log record's key-value pairs into a JSON line.
+struct JsonFields<'a>(&'a mut Map<String, Value>);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(number) = value.to_u64() {
+            number.into()
+        } else if let Some(number) = value.to_i64() {
+            number.into()
+        } else if let Some(flag) = value.to_bool() {
+            flag.into()
+        } else {
+            value.to_string().into()
+        };
+        self.0.insert(key.as_str().to_string(), value);
+        Ok(())
+    }
+}
+
+/// A log file that is rotated once it grows past `max_size` bytes. Old files are
+/// kept as `name.1` (newest) to `name.<keep>`.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    keep: u32,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_size: u64, keep: u32) -> Result<Self> {
+        let file = Self::open_append(path).with_context(|| format!("Failed to open log file {}", path.display()))?;
+        let size = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        Ok(RotatingFile { path: path.to_path_buf(), file, size, max_size, keep })
+    }
+
+    fn open_append(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    fn rotated_path(&self, index: u32) -> PathBuf {
+        let mut name = self.path.as_os_str().to_os_string();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for index in (1..self.keep).rev() {
+                match fs::rename(self.rotated_path(index), self.rotated_path(index + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+        self.file = Self::open_append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    /// Each call carries one whole record, so records are never split across files.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
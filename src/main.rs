@@ -1,17 +1,110 @@
 use anyhow::{Context, Result};
-use chrono::{DateTime, Datelike, TimeZone, Utc};
-use clap::{Parser, Subcommand};
-use log::{error, info, warn, LevelFilter, debug};
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
+use log::{error, info, warn, debug};
 use notify::{Config, Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::cell::OnceCell;
+use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::fs::{self, File};
-use std::io::BufReader;
+use std::io::{self, BufReader};
 use std::path::{Path, PathBuf};
-use std::sync::mpsc::channel;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError};
 use walkdir::WalkDir;
-use mime;
 use mediameta::extract_file_metadata;
-use std::{thread, time::Duration};
+use std::process::ExitCode;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+mod birthtime;
+mod cleanup;
+mod clock;
+mod error;
+mod events;
+mod filename_date;
+mod geocode;
+mod junk;
+mod lock;
+mod logging;
+mod netfs;
+mod progress;
+mod raw;
+mod readiness;
+mod report;
+mod retry;
+mod sanitize;
+mod video;
+mod xmp;
+
+use cleanup::{DeletedRecord, FolderCleanup};
+use clock::ClockOffsets;
+use error::SortError;
+use events::{EventCandidate, EventFolder, EventGrouping};
+use filename_date::FilenameDateParser;
+use geocode::{Geocoder, Location};
+use junk::{JunkFilter, JunkPolicy};
+use lock::{InstanceLock, LOCK_FILE_NAME};
+use logging::{LogFormat, LogSettings};
+use progress::{Progress, ProgressMode};
+use readiness::{Ready, Readiness, ReadinessCheck};
+use report::Report;
+use retry::{Retry, RetryQueue, RETRY_FILE_NAME};
+use sanitize::{PathProfile, Sanitizer, probe_case_insensitive};
+use serde::Serialize;
+
+/// Options for retrying failed files in the long-running commands.
+#[derive(clap::Args, Debug)]
+struct RetryArgs {
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..), help = "How many times a failing file is tried before giving up on it")]
+    retry_attempts: u32,
+    #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u32).range(1..), help = "Seconds before the first retry, doubling after each further failure (up to an hour)")]
+    retry_delay: u32,
+    #[arg(long, default_value = "failed", help = "Folder in the destination that files given up on are moved to (move mode only)")]
+    failed_folder: String,
+}
+
+/// Options deciding when a new file is completely written, for the long-running commands.
+#[derive(clap::Args, Debug)]
+struct ReadinessArgs {
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "quiet,temp-suffix", help = "Checks a file must pass before it is sorted: quiet, exclusive-open, sentinel, temp-suffix")]
+    ready_checks: Vec<ReadinessCheck>,
+    #[arg(long, default_value_t = 1.5, value_parser = parse_quiet_period, help = "Seconds a file's size and modification time must stay unchanged (quiet check)")]
+    quiet_period: f64,
+    #[arg(long, default_value_t = 1800, value_parser = clap::value_parser!(u64).range(..=MAX_READY_SECONDS), help = "Seconds to wait for a file to pass the quiet and exclusive-open checks before skipping it")]
+    ready_timeout: u64,
+    #[arg(long, help = "Files last modified more than this many seconds ago are sorted without waiting")]
+    max_age: Option<u64>,
+    #[arg(long, value_delimiter = ',', default_value = ".part,.crdownload,.tmp,~", help = "File name suffixes of files still being written (temp-suffix check)")]
+    temp_suffixes: Vec<String>,
+    #[arg(long, default_value = ".done", help = "Suffix of the sentinel file marking a file as complete, e.g. photo.jpg.done (sentinel check)")]
+    sentinel_suffix: String,
+}
+
+/// Longest `--quiet-period` and `--ready-timeout` accepted, one week.
+const MAX_READY_SECONDS: u64 = 7 * 24 * 60 * 60;
+
+fn parse_quiet_period(value: &str) -> Result<f64, String> {
+    let seconds: f64 = value.parse().map_err(|e| format!("{}", e))?;
+    if !seconds.is_finite() || seconds < 0.0 || seconds > MAX_READY_SECONDS as f64 {
+        return Err(format!("must be a number of seconds from 0 to {}", MAX_READY_SECONDS));
+    }
+    Ok(seconds)
+}
+
+impl ReadinessArgs {
+    fn readiness(&self) -> Readiness {
+        Readiness {
+            checks: self.ready_checks.clone(),
+            quiet_period: Duration::from_secs_f64(self.quiet_period),
+            timeout: Duration::from_secs(self.ready_timeout),
+            max_age: self.max_age.map(Duration::from_secs),
+            temp_suffixes: self.temp_suffixes.clone(),
+            sentinel_suffix: self.sentinel_suffix.clone(),
+        }
+    }
+}
 
 #[derive(clap::Args, Debug)]
 struct SharedArgs {
@@ -27,10 +120,519 @@ struct SharedArgs {
     camera_model_prefix: bool,
     #[arg(long, help = "Manually specify camera model")]
     manual_camera_model: Option<String>,
-    #[arg(long, default_value_t = false, help = "Copy files instead of moving (default is move)")]
+    #[arg(long, default_value_t = false, help = "Copy files instead of moving (default is move). The source is left untouched.")]
     copy: bool,
+    #[arg(long, default_value_t = false, requires = "copy", help = "In copy mode, still delete empty folders in the source (always done when moving)")]
+    cleanup_empty_folders: bool,
+    #[arg(long, value_delimiter = ',', help = "Folders never deleted by empty-folder cleanup: a path relative to the source, or a folder name matched at any depth (e.g. DCIM)")]
+    protect_folder: Vec<PathBuf>,
+    #[arg(long, value_enum, default_value_t = JunkPolicy::Ignore, help = "What to do with built-in junk (.DS_Store, ._*, Thumbs.db, desktop.ini, .Trashes, ...)")]
+    junk_policy: JunkPolicy,
+    #[arg(long, value_delimiter = ',', help = "Extra junk file or folder names, matched case-insensitively; * matches any characters (e.g. '*.lrv,*.thm')")]
+    junk: Vec<String>,
+    #[arg(long, value_enum, default_value_t = JunkPolicy::Ignore, help = "What to do with junk matching --junk")]
+    junk_extra_policy: JunkPolicy,
+    #[arg(long, default_value_t = false, help = "Treat folders holding only junk files under the ignore or delete policy as empty, deleting the junk with the folder")]
+    junk_is_empty: bool,
+    #[arg(long, value_enum, default_value_t = DateFolders::Day, help = "How deep the date folders go: year, month or day (e.g. month gives YYYY/MM)")]
+    date_folders: DateFolders,
     #[arg(long, default_value_t = false, help = "Keep original filenames instead of renaming to ISO timestamp (default is rename)")]
     keep_names: bool,
+    #[arg(long, default_value_t = false, help = "Lowercase file extensions and use canonical spellings (jpeg -> jpg, tif -> tiff, qt -> mov)")]
+    normalize_extensions: bool,
+    #[arg(long, value_enum, value_delimiter = ',', help = "Metadata tokens to add as extra folders after the date/camera folders (e.g. lens,focal-length)")]
+    folder_tokens: Vec<MetadataToken>,
+    #[arg(long, value_enum, value_delimiter = ',', help = "Metadata tokens to append to renamed files (e.g. focal-length,iso gives YYYY-MM-DDTHH-MM-SS_50mm_ISO3200.jpg)")]
+    filename_tokens: Vec<MetadataToken>,
+    #[arg(long, help = "GeoNames cities file (e.g. cities1000.txt) used for offline reverse geocoding of GPS tags")]
+    geonames: Option<PathBuf>,
+    #[arg(long, requires = "geonames", help = "GeoNames countryInfo.txt file for country names (default: ISO country codes)")]
+    geonames_countries: Option<PathBuf>,
+    #[arg(long, requires = "geonames", help = "GeoNames admin1CodesASCII.txt file for region names (default: admin1 codes)")]
+    geonames_admin1: Option<PathBuf>,
+    #[arg(long, value_enum, value_delimiter = ',', requires = "geonames", help = "Location folders to add after the date folders (default: country,region,city, giving YYYY/MM/DD/Italy/Tuscany/Florence)")]
+    location_folders: Vec<LocationLevel>,
+    #[arg(long, default_value = "Unknown_Location", help = "Folder used in place of the location for files without GPS data or a nearby place")]
+    location_placeholder: String,
+    #[arg(long, default_value_t = 50.0, value_parser = parse_location_distance, help = "Maximum distance in km from the nearest known place for a location to be used")]
+    location_max_distance: f64,
+    #[arg(long = "filename-date-pattern", value_name = "REGEX", help = "Extra regex for dates in file or folder names, with (?P<year>..), (?P<month>..) and optional day/hour/minute/second groups. Can be repeated.")]
+    filename_date_patterns: Vec<String>,
+    #[arg(long, default_value_t = false, help = "Do not look for dates in file and folder names before falling back to file timestamps")]
+    no_filename_dates: bool,
+    #[arg(long, value_enum, value_delimiter = ',', help = "Order of date sources to try (default: exif,video,xmp,filename,ctime, or mtime with --use-modified)")]
+    date_sources: Vec<DateSource>,
+    #[arg(long, default_value_t = false, help = "Never fall back to file timestamps. Files that cannot be dated from their metadata or name are sorted into the undated folder")]
+    require_date_source: bool,
+    #[arg(long, help = "Append a JSON line per processed file to this report, including which date source was used")]
+    report: Option<PathBuf>,
+    #[arg(long, help = "File of per-camera clock corrections (lines of: <camera model or serial> <offset, e.g. -1h12m> [from] [until])")]
+    clock_offsets: Option<PathBuf>,
+    #[arg(long, default_value = "1900-01-01", value_parser = parse_date_arg, help = "Dates before this (YYYY-MM-DD) are implausible and the next date source is tried")]
+    min_date: NaiveDate,
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(i64).range(0..=36500), help = "Dates more than this many days in the future are implausible")]
+    max_future_days: i64,
+    #[arg(long = "reject-date", value_delimiter = ',', default_value = "1904-01-01,1970-01-01,2000-01-01", value_parser = parse_date_arg, help = "Days that are treated as unset clocks (epoch zero or factory defaults) rather than real dates")]
+    reject_dates: Vec<NaiveDate>,
+    #[arg(long, default_value = "undated", help = "Folder in the destination for media files without a plausible date")]
+    undated_folder: String,
+    #[arg(long, value_enum, default_value = if cfg!(windows) { "smb" } else { "posix" }, help = "Filesystem rules for generated folder and file names: posix, fat (FAT32/exFAT) or smb (Windows shares)")]
+    path_profile: PathProfile,
+    #[arg(long, default_value_t = 255, value_parser = clap::value_parser!(u16).range(16..), help = "Maximum length of each generated folder or file name")]
+    max_component_length: u16,
+    #[arg(long, value_enum, default_value = "auto", help = "Whether destination names differing only in case collide: auto (probe the destination), sensitive or insensitive")]
+    destination_case: DestinationCase,
+    #[arg(long, default_value_t = false, conflicts_with = "copy", help = "Also lock the source directory, so no other instance sorts from it (the destination is always locked). Cannot be used with --copy")]
+    lock_source: bool,
+    #[arg(long, default_value_t = false, help = "Allow the destination to be inside the source and skip it when scanning and watching")]
+    exclude_destination: bool,
+    #[arg(long, default_value_t = false, help = "Stop at the first file that cannot be sorted")]
+    fail_fast: bool,
+    #[arg(long, value_enum, default_value_t = ProgressMode::Auto, help = "Progress while sorting a scanned directory: a bar on terminals, summary lines otherwise (auto), or bar, log or off")]
+    progress: ProgressMode,
+    #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u64).range(1..), help = "Seconds between progress summary lines when no progress bar is shown")]
+    progress_interval: u64,
+}
+
+fn parse_date_arg(value: &str) -> Result<NaiveDate, chrono::ParseError> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+}
+
+/// Longest accepted event gap, ten years in hours.
+const MAX_EVENT_GAP_HOURS: f64 = 87_600.0;
+
+/// No two places on Earth are further apart than half its circumference.
+const MAX_DISTANCE_KM: f64 = 20_038.0;
+
+fn parse_event_gap(value: &str) -> Result<f64, String> {
+    parse_positive(value, MAX_EVENT_GAP_HOURS, "hours")
+}
+
+/// Largest `--location-max-distance`. The geocoder searches one-degree cells, so
+/// larger distances would scan most of the globe for every file.
+const MAX_LOCATION_DISTANCE_KM: f64 = 1_000.0;
+
+fn parse_location_distance(value: &str) -> Result<f64, String> {
+    parse_positive(value, MAX_LOCATION_DISTANCE_KM, "km")
+}
+
+fn parse_distance(value: &str) -> Result<f64, String> {
+    parse_positive(value, MAX_DISTANCE_KM, "km")
+}
+
+/// Parses a finite number greater than 0 and at most `max`.
+fn parse_positive(value: &str, max: f64, unit: &str) -> Result<f64, String> {
+    let number: f64 = value.parse().map_err(|e| format!("{}", e))?;
+    if !number.is_finite() || number <= 0.0 || number > max {
+        return Err(format!("must be a number of {} greater than 0 and at most {}", unit, max));
+    }
+    Ok(number)
+}
+
+/// Plausibility bounds that dates from any source must fall within.
+#[derive(Debug)]
+struct DateBounds {
+    min_date: NaiveDate,
+    max_future: chrono::Duration,
+    reject_dates: Vec<NaiveDate>,
+}
+
+impl DateBounds {
+    fn check(&self, date_time: DateTime<Utc>) -> Result<()> {
+        let date = date_time.date_naive();
+        if self.reject_dates.contains(&date) {
+            anyhow::bail!("{} looks like an unset camera clock", date);
+        }
+        if date < self.min_date {
+            anyhow::bail!("{} is before the minimum date {}", date, self.min_date);
+        }
+        if date_time > Utc::now() + self.max_future {
+            anyhow::bail!("{} is in the future", date_time);
+        }
+        Ok(())
+    }
+}
+
+/// Where a file's date can come from, tried in the order given by `--date-sources`.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum DateSource {
+    Exif,
+    Video,
+    Xmp,
+    Filename,
+    #[value(name = "ctime", alias = "created")]
+    Created,
+    #[value(name = "mtime", alias = "modified")]
+    Modified,
+}
+
+impl DateSource {
+    fn is_file_timestamp(self) -> bool {
+        matches!(self, DateSource::Created | DateSource::Modified)
+    }
+}
+
+impl std::fmt::Display for DateSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = self.to_possible_value().expect("no skipped variants");
+        f.write_str(value.get_name())
+    }
+}
+
+/// How many levels of date folders to create.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum DateFolders {
+    Year,
+    Month,
+    Day,
+}
+
+/// A level of location folders. Folders are always created from country down to city.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum LocationLevel {
+    Country,
+    Region,
+    City,
+}
+
+/// How destination names that differ only in case are treated.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum DestinationCase {
+    /// Probe the destination at startup.
+    Auto,
+    Sensitive,
+    Insensitive,
+}
+
+/// EXIF values beyond date and camera model that can be used in the destination layout.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum MetadataToken {
+    Lens,
+    FocalLength,
+    Iso,
+    ExposureProgram,
+    Dimensions,
+    Orientation,
+    Software,
+}
+
+/// Options shared by every processing mode, resolved once from the command line.
+#[derive(Debug)]
+struct ProcessOptions {
+    source: PathBuf,
+    destination: String,
+    date_sources: Vec<DateSource>,
+    use_camera_model: bool,
+    camera_model_is_prefix: bool,
+    manual_camera_model: Option<String>,
+    copy_files: bool,
+    /// Deletes source folders emptied by sorting. Copy mode leaves the source
+    /// untouched unless cleanup was asked for explicitly.
+    folder_cleanup: Option<FolderCleanup>,
+    /// System files that are never sorted, each handled by its list's policy.
+    junk: JunkFilter,
+    keep_names: bool,
+    normalize_extensions: bool,
+    /// Whether collision checks ignore case, because the destination does or
+    /// because extensions are being normalised.
+    case_insensitive_names: bool,
+    folder_tokens: Vec<MetadataToken>,
+    filename_tokens: Vec<MetadataToken>,
+    geocoder: Option<Geocoder>,
+    date_folders: DateFolders,
+    /// Location folders to create, empty without a geocoder.
+    location_levels: Vec<LocationLevel>,
+    location_placeholder: String,
+    event_grouping: Option<EventGrouping>,
+    filename_dates: FilenameDateParser,
+    report: Option<Report>,
+    clock_offsets: Option<ClockOffsets>,
+    date_bounds: DateBounds,
+    undated_folder: String,
+    sanitizer: Sanitizer,
+    /// Held for as long as the options are in use and released on drop.
+    _locks: Vec<InstanceLock>,
+    /// Files already copied by this process, so repeated watcher events and rescans
+    /// do not copy them again unless they change.
+    copied: Mutex<HashMap<PathBuf, FileSignature>>,
+    /// Paths inside the source that are never scanned, such as a nested destination
+    /// or the report file, so the tool does not pick up its own output.
+    excluded_paths: Vec<PathBuf>,
+    fail_fast: bool,
+    progress: ProgressMode,
+    progress_interval: Duration,
+    /// Failed files waiting to be tried again, in `monitor` and `poll`.
+    retry_queue: Option<RetryQueue>,
+    failed_folder: String,
+    /// When files appearing in `monitor`, `poll` and `watch` are ready to be sorted.
+    readiness: Readiness,
+    /// Number of files that could not be sorted, which decides the exit code.
+    failures: AtomicUsize,
+}
+
+/// Identifies a version of a file by its size and modification time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileSignature {
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+impl FileSignature {
+    fn read(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(FileSignature { size: metadata.len(), modified: metadata.modified().ok() })
+    }
+}
+
+impl ProcessOptions {
+    fn from_args(shared: &SharedArgs) -> Result<Self> {
+        let mut location_levels = shared.location_folders.clone();
+        location_levels.sort();
+        location_levels.dedup();
+        if location_levels.is_empty() && shared.geonames.is_some() {
+            location_levels = vec![LocationLevel::Country, LocationLevel::Region, LocationLevel::City];
+        }
+        let geocoder = match &shared.geonames {
+            Some(cities) => Some(Geocoder::load(
+                cities,
+                shared.geonames_countries.as_deref(),
+                shared.geonames_admin1.as_deref(),
+                shared.location_max_distance,
+            )?),
+            None => None,
+        };
+
+        let mut date_sources = if shared.date_sources.is_empty() {
+            let file_timestamp = if shared.use_modified { DateSource::Modified } else { DateSource::Created };
+            vec![DateSource::Exif, DateSource::Video, DateSource::Xmp, DateSource::Filename, file_timestamp]
+        } else {
+            shared.date_sources.clone()
+        };
+        if shared.no_filename_dates {
+            date_sources.retain(|&source| source != DateSource::Filename);
+        }
+        if shared.require_date_source {
+            date_sources.retain(|source| !source.is_file_timestamp());
+        }
+        if date_sources.is_empty() {
+            anyhow::bail!("No date sources left to try");
+        }
+        info!("Date sources: {}", date_sources.iter().map(ToString::to_string).collect::<Vec<_>>().join(" -> "));
+
+        let excluded_paths = excluded_paths(shared)?;
+        let filename_dates = FilenameDateParser::new(&shared.filename_date_patterns)?;
+        let clock_offsets = shared.clock_offsets.as_deref().map(ClockOffsets::load).transpose()?;
+
+        // Locks and the report are only written once the arguments are known to be valid.
+        let mut locks = vec![InstanceLock::acquire(Path::new(&shared.destination))?];
+        if shared.lock_source {
+            locks.push(InstanceLock::acquire(Path::new(&shared.source))?);
+        }
+        let report = shared.report.as_deref().map(Report::open).transpose()?;
+        let junk = JunkFilter::new(shared.junk_policy, &shared.junk, shared.junk_extra_policy);
+        if let Some(report) = &report {
+            report.record(&RunRecord {
+                action: "run_started",
+                source: PathBuf::from(&shared.source),
+                destination: PathBuf::from(&shared.destination),
+                mode: if shared.copy { "copy" } else { "move" },
+                cleanup_empty_folders: !shared.copy || shared.cleanup_empty_folders,
+            });
+        }
+
+        let case_insensitive_dest = match shared.destination_case {
+            DestinationCase::Sensitive => false,
+            DestinationCase::Insensitive => true,
+            DestinationCase::Auto => match probe_case_insensitive(Path::new(&shared.destination)) {
+                Ok(insensitive) => insensitive,
+                Err(e) => {
+                    warn!("Could not probe case sensitivity of {}: {}. Assuming names are case-insensitive.", shared.destination, e);
+                    true
+                }
+            },
+        };
+        info!("Destination {} is {}", shared.destination, if case_insensitive_dest { "case-insensitive" } else { "case-sensitive" });
+
+        Ok(ProcessOptions {
+            source: PathBuf::from(&shared.source),
+            destination: shared.destination.clone(),
+            date_sources,
+            use_camera_model: !shared.no_camera_model,
+            camera_model_is_prefix: shared.camera_model_prefix,
+            manual_camera_model: shared.manual_camera_model.clone(),
+            copy_files: shared.copy,
+            folder_cleanup: (!shared.copy || shared.cleanup_empty_folders)
+                .then(|| FolderCleanup::new(Path::new(&shared.source), &shared.protect_folder, junk.clone(), shared.junk_is_empty)),
+            junk,
+            keep_names: shared.keep_names,
+            normalize_extensions: shared.normalize_extensions,
+            case_insensitive_names: case_insensitive_dest || shared.normalize_extensions,
+            folder_tokens: shared.folder_tokens.clone(),
+            filename_tokens: shared.filename_tokens.clone(),
+            date_folders: shared.date_folders,
+            location_levels,
+            geocoder,
+            location_placeholder: shared.location_placeholder.clone(),
+            event_grouping: None,
+            filename_dates,
+            report,
+            clock_offsets,
+            date_bounds: DateBounds {
+                min_date: shared.min_date,
+                max_future: chrono::Duration::days(shared.max_future_days),
+                reject_dates: shared.reject_dates.clone(),
+            },
+            undated_folder: shared.undated_folder.clone(),
+            sanitizer: Sanitizer::new(shared.path_profile, shared.max_component_length.into()),
+            _locks: locks,
+            copied: Mutex::new(HashMap::new()),
+            excluded_paths,
+            fail_fast: shared.fail_fast,
+            progress: shared.progress,
+            progress_interval: Duration::from_secs(shared.progress_interval),
+            retry_queue: None,
+            failed_folder: String::new(),
+            readiness: Readiness::default(),
+            failures: AtomicUsize::new(0),
+        })
+    }
+
+    fn enable_retries(&mut self, retry: &RetryArgs) -> Result<()> {
+        let base_delay = chrono::Duration::seconds(retry.retry_delay.into());
+        self.retry_queue = Some(RetryQueue::load(Path::new(&self.destination), retry.retry_attempts, base_delay)?);
+        self.failed_folder = retry.failed_folder.clone();
+        Ok(())
+    }
+
+    /// Whether this version of the file was already copied by this process.
+    fn already_copied(&self, file_path: &Path, signature: &FileSignature) -> bool {
+        let key = std::path::absolute(file_path).unwrap_or_else(|_| file_path.to_path_buf());
+        self.copied.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).get(&key) == Some(signature)
+    }
+
+    /// Remembers a copied file. Paths are made absolute, since watcher events and
+    /// directory scans name the same file differently.
+    fn mark_copied(&self, file_path: &Path, signature: FileSignature) {
+        let key = std::path::absolute(file_path).unwrap_or_else(|_| file_path.to_path_buf());
+        self.copied.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(key, signature);
+    }
+
+    /// Logs and counts a file that could not be sorted, and schedules a retry when
+    /// retries are enabled.
+    fn record_failure(&self, file_path: &Path, error: &SortError) {
+        warn!(
+            event = "failed", path:% = file_path.display(), error = error.category(), message:% = error;
+            "Failed to process file {} ({} error): {}", file_path.display(), error.category(), error
+        );
+        self.failures.fetch_add(1, Ordering::Relaxed);
+        if let Some(report) = &self.report {
+            report.record(&FailureRecord {
+                source: file_path,
+                action: "failed",
+                error: error.category(),
+                message: error.to_string(),
+            });
+        }
+        if let Some(queue) = &self.retry_queue {
+            match queue.schedule(file_path, error) {
+                Retry::At(when) => info!(event = "retry_scheduled", path:% = file_path.display(), retry_at:% = when; "Will retry {} at {}", file_path.display(), when),
+                Retry::GiveUp => quarantine_file(file_path, self),
+            }
+        }
+    }
+
+    /// Logs and counts a file or folder the directory walk could not read, so an
+    /// unreadable subfolder is reported instead of silently skipped. It is not
+    /// queued for retries; the next scan or rescan visits it again.
+    fn record_walk_error(&self, error: walkdir::Error) {
+        let path = error.path().map(Path::to_path_buf).unwrap_or_else(|| self.source.clone());
+        let source = error.into_io_error().unwrap_or_else(|| io::Error::other("filesystem loop"));
+        let error = SortError::io(&path, source);
+        warn!(
+            event = "failed", path:% = path.display(), error = error.category(), message:% = error;
+            "Failed to read {} ({} error): {}", path.display(), error.category(), error
+        );
+        self.failures.fetch_add(1, Ordering::Relaxed);
+        if let Some(report) = &self.report {
+            report.record(&FailureRecord { source: &path, action: "failed", error: error.category(), message: error.to_string() });
+        }
+    }
+
+    /// With `--fail-fast`, whether a failure means no more files should be processed.
+    fn should_stop(&self) -> bool {
+        self.fail_fast && self.failures.load(Ordering::Relaxed) > 0
+    }
+
+    /// Whether a path must not be scanned: the tool's own output, or a junk folder
+    /// such as `.Trashes` and anything inside it.
+    fn is_excluded(&self, path: &Path) -> bool {
+        if is_state_file(path) || self.junk.excludes(path, &self.source) {
+            return true;
+        }
+        if self.excluded_paths.is_empty() {
+            return false;
+        }
+        let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        self.excluded_paths.iter().any(|excluded| absolute.starts_with(excluded))
+    }
+
+    fn needs_metadata_tokens(&self) -> bool {
+        !self.folder_tokens.is_empty() || !self.filename_tokens.is_empty()
+    }
+}
+
+/// Whether the file is a lock file or retry queue written by ShutterSort itself.
+fn is_state_file(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name == LOCK_FILE_NAME || name == RETRY_FILE_NAME)
+}
+
+/// Finds the tool's own output inside the source. A destination inside the source
+/// would have sorted files picked up again, so it is refused unless
+/// `--exclude-destination` is given.
+fn excluded_paths(shared: &SharedArgs) -> Result<Vec<PathBuf>> {
+    let source = fs::canonicalize(&shared.source).with_context(|| format!("Source directory {} does not exist", shared.source))?;
+    let destination = resolve_path(Path::new(&shared.destination)).with_context(|| format!("Failed to resolve destination {}", shared.destination))?;
+    let mut excluded = Vec::new();
+
+    if destination == source {
+        anyhow::bail!("Source and destination are the same directory ({})", source.display());
+    }
+    if destination.starts_with(&source) {
+        if !shared.exclude_destination {
+            anyhow::bail!(
+                "Destination {} is inside the source {}, so sorted files would be processed again. Choose another destination or pass --exclude-destination.",
+                destination.display(),
+                source.display()
+            );
+        }
+        info!("Excluding destination {} from scanning the source", destination.display());
+        excluded.push(std::path::absolute(&shared.destination)?);
+        excluded.push(destination);
+    }
+
+    if let Some(report_path) = &shared.report
+        && let Ok(report) = fs::canonicalize(report_path)
+        && report.starts_with(&source)
+    {
+        excluded.push(std::path::absolute(report_path)?);
+        excluded.push(report);
+    }
+    Ok(excluded)
+}
+
+/// Canonicalises a path that may not exist yet, such as a destination created on
+/// the first run, by resolving its nearest existing ancestor.
+fn resolve_path(path: &Path) -> io::Result<PathBuf> {
+    match fs::canonicalize(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let absolute = std::path::absolute(path)?;
+            match (absolute.parent(), absolute.file_name()) {
+                (Some(parent), Some(name)) => Ok(resolve_path(parent)?.join(name)),
+                _ => Err(e),
+            }
+        }
+        result => result,
+    }
 }
 
 #[derive(Parser)]
@@ -41,6 +643,14 @@ struct Cli {
 
     #[arg(short, long, action = clap::ArgAction::SetTrue, global = true, help = "Enable verbose logging (debug level)")]
     verbose: bool,
+    #[arg(long, value_enum, default_value_t = LogFormat::Text, global = true, help = "Log format: human-readable text or one JSON object per line")]
+    log_format: LogFormat,
+    #[arg(long, global = true, help = "Write logs to this file instead of stderr")]
+    log_file: Option<PathBuf>,
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..), global = true, help = "Size in MB at which the log file is rotated")]
+    log_max_size: u64,
+    #[arg(long, default_value_t = 5, global = true, help = "Number of rotated log files to keep")]
+    log_keep: u32,
 }
 
 #[derive(Subcommand)]
@@ -49,11 +659,21 @@ enum Commands {
     Once {
         #[clap(flatten)]
         shared: SharedArgs,
+        #[arg(long, default_value_t = false, help = "Group files into event folders (e.g. YYYY/2024-05-03_to_05-05) instead of day folders, starting a new event at gaps between shots")]
+        events: bool,
+        #[arg(long, default_value_t = 3.0, requires = "events", value_parser = parse_event_gap, help = "Gap in hours between shots that starts a new event")]
+        event_gap: f64,
+        #[arg(long, requires = "events", value_parser = parse_distance, help = "Also start a new event when a shot is more than this many km from the previous GPS position")]
+        event_split_distance: Option<f64>,
     },
     #[command(about = "Monitor source directory and automatically process new files")]
     Monitor {
         #[clap(flatten)]
         shared: SharedArgs,
+        #[clap(flatten)]
+        retry: RetryArgs,
+        #[clap(flatten)]
+        readiness: ReadinessArgs,
     },
     #[command(about = "Monitor source directory by polling and process files")]
     Poll {
@@ -61,219 +681,468 @@ enum Commands {
         shared: SharedArgs,
         #[arg(long, help = "Polling interval in seconds", default_value_t = 5, value_parser = clap::value_parser!(u64).range(1..))]
         interval: u64,
+        #[clap(flatten)]
+        retry: RetryArgs,
+        #[clap(flatten)]
+        readiness: ReadinessArgs,
+    },
+    #[command(about = "Monitor source directory with file system events plus periodic rescans, falling back to polling where events are not delivered")]
+    Watch {
+        #[clap(flatten)]
+        shared: SharedArgs,
+        #[arg(long, help = "Seconds between rescans that catch files the watcher missed", default_value_t = 300, value_parser = clap::value_parser!(u64).range(1..))]
+        rescan_interval: u64,
+        #[arg(long, help = "Polling interval in seconds when falling back to polling", default_value_t = 5, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+        #[clap(flatten)]
+        retry: RetryArgs,
+        #[clap(flatten)]
+        readiness: ReadinessArgs,
+    },
+    #[command(about = "Compute a clock offset from two photos taken at the same moment on different cameras")]
+    ClockOffset {
+        #[arg(long, help = "Photo from the camera with the correct clock")]
+        reference: PathBuf,
+        #[arg(long, help = "Photo from the camera whose clock needs correcting")]
+        target: PathBuf,
     },
 }
 
-const MIMNIMUM_FILE_STABILITY_CHECKS: u32 = 3;
-const FILE_CHECK_INTERVAL: Duration = Duration::from_millis(500);
-const MAX_FILE_CHECK_ATTEMPTS: u32 = 3600; // 3600 * 500ms = 30 minutes
+/// How often `monitor` looks for due retries while no events arrive.
+const RETRY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Exit code when the run completed but some files could not be sorted.
+const EXIT_PARTIAL_FAILURE: u8 = 1;
+/// Exit code when the run could not start or was aborted. Also used by clap for invalid arguments.
+const EXIT_FATAL: u8 = 2;
 
-fn main() -> Result<()> {
+fn main() -> ExitCode {
     let cli = Cli::parse();
 
-    let default_log_level = if cli.verbose {
-        LevelFilter::Debug.as_str() 
-    } else {
-        LevelFilter::Info.as_str()
+    let log_settings = LogSettings {
+        verbose: cli.verbose,
+        format: cli.log_format,
+        file: cli.log_file.as_deref(),
+        max_file_size: cli.log_max_size * 1024 * 1024,
+        keep_files: cli.log_keep,
     };
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default_log_level)).init();
+    if let Err(e) = logging::init(&log_settings) {
+        eprintln!("Error: {:?}", e);
+        return ExitCode::from(EXIT_FATAL);
+    }
 
-    match &cli.command {
-        Commands::Once { shared } => {
-            process_directory(&shared.source, &shared.destination, shared.use_modified, !shared.no_camera_model, shared.camera_model_prefix, shared.manual_camera_model.as_ref(), shared.copy, shared.keep_names)?;
-        }
-        Commands::Monitor { shared } => {
-            monitor_directory(&shared.source, &shared.destination, shared.use_modified, !shared.no_camera_model, shared.camera_model_prefix, shared.manual_camera_model.as_ref(), shared.copy, shared.keep_names)?;
+    match run(&cli) {
+        Ok(0) => ExitCode::SUCCESS,
+        Ok(failures) => {
+            error!("{} file(s) could not be sorted", failures);
+            ExitCode::from(EXIT_PARTIAL_FAILURE)
         }
-        Commands::Poll { shared, interval } => {
-            poll_directory(
-                &shared.source,
-                &shared.destination,
-                shared.use_modified,
-                !shared.no_camera_model,
-                shared.camera_model_prefix,
-                shared.manual_camera_model.as_ref(),
-                shared.copy,
-                shared.keep_names,
-                *interval,
-            )?;
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(EXIT_FATAL)
         }
     }
-    Ok(())
 }
 
-fn process_directory(source: &str, destination: &str, use_modified: bool, use_camera_model: bool, camera_model_is_prefix: bool, manual_camera_model: Option<&String>, copy_files: bool, keep_names: bool) -> Result<()> {
+/// Runs the command and returns how many files could not be sorted.
+fn run(cli: &Cli) -> Result<usize> {
+    let options = match &cli.command {
+        Commands::Once { shared, events, event_gap, event_split_distance } => {
+            let mut options = ProcessOptions::from_args(shared)?;
+            if *events {
+                options.event_grouping = Some(EventGrouping {
+                    gap: chrono::Duration::seconds((event_gap * 3600.0) as i64),
+                    split_distance_km: *event_split_distance,
+                });
+            }
+            process_directory(&shared.source, &options)?;
+            options
+        }
+        Commands::Monitor { shared, retry, readiness } => {
+            let mut options = ProcessOptions::from_args(shared)?;
+            options.enable_retries(retry)?;
+            options.readiness = readiness.readiness();
+            monitor_directory(&shared.source, &options)?;
+            options
+        }
+        Commands::Poll { shared, interval, retry, readiness } => {
+            let mut options = ProcessOptions::from_args(shared)?;
+            options.enable_retries(retry)?;
+            options.readiness = readiness.readiness();
+            poll_directory(&shared.source, &options, *interval)?;
+            options
+        }
+        Commands::Watch { shared, rescan_interval, interval, retry, readiness } => {
+            let mut options = ProcessOptions::from_args(shared)?;
+            options.enable_retries(retry)?;
+            options.readiness = readiness.readiness();
+            watch_directory(&shared.source, &options, Duration::from_secs(*rescan_interval), *interval)?;
+            options
+        }
+        Commands::ClockOffset { reference, target } => {
+            print_clock_offset(reference, target)?;
+            return Ok(0);
+        }
+    };
+    Ok(options.failures.load(Ordering::Relaxed))
+}
+
+fn process_directory(source: &str, options: &ProcessOptions) -> Result<()> {
     info!("Processing directory: {}", source);
     let source_path = Path::new(source);
     let mut files_to_process: Vec<PathBuf> = Vec::new();
 
-    for entry in WalkDir::new(source_path).into_iter().filter_map(|e| e.ok()) {
+    for entry in WalkDir::new(source_path).into_iter().filter_entry(|e| !options.is_excluded(e.path())).filter_map(|e| e.map_err(|e| options.record_walk_error(e)).ok()) {
+        if options.should_stop() {
+            break;
+        }
         if entry.file_type().is_file() {
+            if let Ready::NotYet(reason) = options.readiness.quick_check(entry.path()) {
+                debug!("File {} is not ready ({}). Skipping for now.", entry.path().display(), reason);
+                continue;
+            }
+            info!(event = "discovered", path:% = entry.path().display(); "Found file {}", entry.path().display());
             files_to_process.push(entry.path().to_path_buf());
         }
     }
+    if options.should_stop() {
+        warn!("Stopping after the first failure (--fail-fast)");
+        return Ok(());
+    }
 
+    // Grouping needs every file's date up front. The metadata read for it is kept, so
+    // each file's EXIF is only read once.
+    let mut metadata: HashMap<PathBuf, MediaMetadata> = HashMap::new();
+    let events = match &options.event_grouping {
+        Some(grouping) => {
+            for file_path in files_to_process.iter().filter(|file_path| is_media_file(file_path)) {
+                metadata.insert(file_path.clone(), MediaMetadata::read(file_path, options));
+            }
+            let candidates = metadata
+                .iter()
+                .filter_map(|(path, media)| {
+                    let (date_time, _) = media.date.as_ref().ok()?;
+                    Some(EventCandidate { path: path.clone(), date_time: *date_time, coordinates: media.coordinates })
+                })
+                .collect();
+            events::group_events(candidates, grouping)
+        }
+        None => HashMap::new(),
+    };
+
+    let mut progress = Progress::start(&files_to_process, options.progress, options.progress_interval);
     for file_path in files_to_process {
-        match process_file(&file_path, destination, use_modified, use_camera_model, camera_model_is_prefix, manual_camera_model, copy_files, keep_names) {
-            Ok(_) => {},
-            Err(e) => warn!("Failed to process file {}: {}", file_path.display(), e),
+        progress.start_file(&file_path);
+        let result = process_file(&file_path, options, events.get(&file_path), metadata.remove(&file_path));
+        progress.finish_file();
+        if let Err(e) = result {
+            options.record_failure(&file_path, &e);
+            if options.should_stop() {
+                warn!("Stopping after the first failure (--fail-fast)");
+                break;
+            }
         }
     }
-    delete_empty_folders(source)?;
+    progress.finish();
+    delete_empty_folders(source, options)?;
     info!("Directory processing complete");
     Ok(())
 }
 
-fn monitor_directory(source: &str, destination: &str, use_modified: bool, use_camera_model: bool, camera_model_is_prefix: bool, manual_camera_model: Option<&String>, copy_files: bool, keep_names: bool) -> Result<()> {
+fn monitor_directory(source: &str, options: &ProcessOptions) -> Result<()> {
     info!("Starting to monitor directory: {}", source);
-    // Initial processing of existing files
-    process_directory(source, destination, use_modified, use_camera_model, camera_model_is_prefix, manual_camera_model, copy_files, keep_names)?;
-    // Set up file watcher
+    // Set up the file watcher first, so files arriving during the initial scan queue up as events
     let (tx, rx) = channel();
     let mut watcher = RecommendedWatcher::new(tx, Config::default())?;
     watcher.watch(Path::new(source).as_ref(), RecursiveMode::Recursive)?;
+    // Initial processing of existing files
+    process_directory(source, options)?;
     info!("Watching for changes...");
-    loop {
-        match rx.recv() {
-            Ok(Ok(event)) => handle_fs_event(event, source, destination, use_modified, use_camera_model, camera_model_is_prefix, manual_camera_model, copy_files, keep_names)?,
+    while !options.should_stop() {
+        match rx.recv_timeout(RETRY_CHECK_INTERVAL) {
+            Ok(Ok(event)) => handle_fs_event(event, source, options)?,
             Ok(Err(e)) => error!("Watch error: {:?}", e),
+            Err(RecvTimeoutError::Timeout) => {}
             Err(e) => {
                 error!("Watch channel error: {:?}", e);
                 break;
             }
         }
+        retry_due_files(source, options)?;
     }
     Ok(())
 }
 
-/// Waits for a file's size to stabilize, indicating that a write operation (like a copy) might be complete.
-fn wait_for_file_stability(file_path: &Path) -> Result<()> {
-    if !file_path.exists() {
-        debug!("File {} does not exist at start of stability check.", file_path.display());
-        return Err(anyhow::anyhow!("File does not exist: {}", file_path.display()));
+/// Like `monitor_directory`, but also rescans the source every `rescan_interval` to
+/// catch missed events, and switches to `poll_directory` when events cannot be
+/// relied on: on network filesystems, or once the watcher reports an error.
+fn watch_directory(source: &str, options: &ProcessOptions, rescan_interval: Duration, poll_interval_secs: u64) -> Result<()> {
+    if let Some(fs_type) = netfs::network_filesystem(Path::new(source)) {
+        warn!("Source {} is on a {} filesystem, which does not deliver file system events. Falling back to polling.", source, fs_type);
+        return poll_directory(source, options, poll_interval_secs);
     }
 
-    let mut previous_size = match fs::metadata(file_path) {
-        Ok(meta) => meta.len(),
+    info!("Starting to watch directory: {}. Rescanning every {}s.", source, rescan_interval.as_secs());
+    let (tx, rx) = channel();
+    let watcher = RecommendedWatcher::new(tx, Config::default())
+        .and_then(|mut watcher| watcher.watch(Path::new(source), RecursiveMode::Recursive).map(|_| watcher));
+    let watcher = match watcher {
+        Ok(watcher) => watcher,
         Err(e) => {
-            if e.kind() == std::io::ErrorKind::NotFound {
-                 debug!("File {} not found when trying to get initial metadata: {}", file_path.display(), e);
-            } else {
-                 warn!("Failed to get initial metadata for {}: {}. Assuming unstable.", file_path.display(), e);
-            }
-            return Err(anyhow::anyhow!("Failed to get initial metadata for stability check on {}", file_path.display()).context(e));
+            warn!("Could not watch {}: {}. Falling back to polling.", source, e);
+            return poll_directory(source, options, poll_interval_secs);
         }
     };
-    let mut stable_checks_count = 0;
-    let mut attempts = 0;
 
-    debug!("Waiting for stability for file: {}", file_path.display());
-
-    loop {
-        thread::sleep(FILE_CHECK_INTERVAL);
-        attempts += 1;
+    process_directory(source, options)?;
+    let mut last_rescan = Instant::now();
+    while !options.should_stop() {
+        let failure = match rx.recv_timeout(RETRY_CHECK_INTERVAL) {
+            Ok(Ok(event)) => {
+                handle_fs_event(event, source, options)?;
+                None
+            }
+            Ok(Err(e)) => Some(format!("Watch error: {:?}", e)),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(e) => Some(format!("Watch channel error: {:?}", e)),
+        };
+        if let Some(failure) = failure {
+            warn!("{}. Falling back to polling.", failure);
+            // Stop the watcher and its unbounded channel, which nothing drains from now on.
+            drop(watcher);
+            drop(rx);
+            return poll_directory(source, options, poll_interval_secs);
+        }
+        retry_due_files(source, options)?;
+        if last_rescan.elapsed() >= rescan_interval {
+            rescan_directory(source, options)?;
+            last_rescan = Instant::now();
+        }
+    }
+    Ok(())
+}
 
-        if !file_path.exists() {
-            debug!("File {} was removed during stability check.", file_path.display());
-            return Err(anyhow::anyhow!("File removed during stability check: {}", file_path.display()));
+/// Processes any files still in the source, for files the watcher missed.
+fn rescan_directory(source: &str, options: &ProcessOptions) -> Result<()> {
+    debug!("Rescanning {} for missed files", source);
+    for entry in WalkDir::new(source).into_iter().filter_entry(|e| !options.is_excluded(e.path())).filter_map(|e| e.map_err(|e| options.record_walk_error(e)).ok()) {
+        if options.should_stop() {
+            break;
+        }
+        if entry.file_type().is_file() && needs_processing(entry.path(), options) {
+            info!(event = "discovered", path:% = entry.path().display(); "Rescan found unprocessed file {}", entry.path().display());
+            process_when_ready(entry.path(), options);
         }
+    }
+    delete_empty_folders(source, options)
+}
 
-        let current_metadata = match fs::metadata(file_path) {
-            Ok(meta) => meta,
-            Err(e) => {
-                warn!("Failed to get metadata for {} during stability check (attempt {}): {}. Assuming unstable.", file_path.display(), attempts, e);
-                previous_size = 0; // Invalidate previous_size to ensure change if file reappears
-                stable_checks_count = 0;
-                if attempts >= MAX_FILE_CHECK_ATTEMPTS {
-                     return Err(anyhow::anyhow!("File {} failed metadata read and max attempts reached", file_path.display()).context(e));
-                }
-                continue; // Try next attempt
-            }
-        };
-        let current_size = current_metadata.len();
+/// Cheap check for whether `process_file` would do anything with a file, so
+/// rescans do not wait for files that were already copied or are skipped anyway.
+fn needs_processing(file_path: &Path, options: &ProcessOptions) -> bool {
+    if !options.copy_files {
+        return true;
+    }
+    is_media_file(file_path) && !FileSignature::read(file_path).is_some_and(|signature| options.already_copied(file_path, &signature))
+}
 
-        debug!("File {}: prev_size={}, current_size={}, stable_checks={}, attempt={}/{}",
-               file_path.display(), previous_size, current_size, stable_checks_count, attempts, MAX_FILE_CHECK_ATTEMPTS);
+fn handle_fs_event(event: Event, source: &str, options: &ProcessOptions) -> Result<()> {
+    use notify::event::{EventKind, ModifyKind, RenameMode};
 
-        if current_size == previous_size {
-            stable_checks_count += 1;
-            if stable_checks_count >= MIMNIMUM_FILE_STABILITY_CHECKS {
-                info!("File {} stabilised with size {} after {} checks ({} attempts).", file_path.display(), current_size, stable_checks_count, attempts);
-                return Ok(());
+    // Directories are only scanned when they appear, not whenever a file inside them changes
+    let (paths, scan_directories) = match event.kind {
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => (Vec::new(), false),
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => (event.paths.last().cloned().into_iter().collect(), true),
+        EventKind::Modify(ModifyKind::Name(_)) | EventKind::Create(_) => (event.paths, true),
+        EventKind::Modify(_) => (event.paths, false),
+        _ => (Vec::new(), false),
+    };
+    for path in paths {
+        if options.should_stop() {
+            warn!("Stopping after the first failure (--fail-fast)");
+            return Ok(());
+        }
+        if options.is_excluded(&path) {
+            continue;
+        }
+        if path.is_file() {
+            info!(event = "discovered", path:% = path.display(); "FS Event for file: {}. Checking stability.", path.display());
+            process_when_ready(&path, options);
+        } else if path.is_dir() && scan_directories {
+            info!("Directory {} appeared. Scanning it for files.", path.display());
+            for entry in WalkDir::new(&path).into_iter().filter_entry(|e| !options.is_excluded(e.path())).filter_map(|e| e.map_err(|e| options.record_walk_error(e)).ok()) {
+                if entry.file_type().is_file() && !options.should_stop() {
+                    process_when_ready(entry.path(), options);
+                }
             }
         } else {
-            debug!("File {} size changed ({} -> {}). Resetting stability counter.",
-                   file_path.display(), previous_size, current_size);
-            previous_size = current_size;
-            stable_checks_count = 0; // Reset counter if size changes
-        }
-
-        if attempts >= MAX_FILE_CHECK_ATTEMPTS {
-            warn!("File {} did not stabilise after {} attempts (total {} seconds). Current size: {}, previous size recorded: {}.",
-                  file_path.display(), attempts, attempts * FILE_CHECK_INTERVAL.as_secs() as u32, current_size, previous_size);
-            return Err(anyhow::anyhow!("File {} did not stabilise after maximum attempts", file_path.display()));
+            debug!("FS Event for non-file path: {}. Ignoring for file processing.", path.display());
         }
     }
+    delete_empty_folders(source, options)?;
+    Ok(())
 }
 
-fn handle_fs_event(event: Event, source: &str, destination: &str, use_modified: bool, use_camera_model: bool, camera_model_is_prefix: bool, manual_camera_model: Option<&String>, copy_files: bool, keep_names: bool) -> Result<()> {
-    if let notify::EventKind::Create(_) | notify::EventKind::Modify(_) = event.kind {
-        for path in event.paths {
-            if path.is_file() {
-                debug!("FS Event for file: {}. Checking stability.", path.display());
+/// What happened to a file handed to `process_when_ready`.
+#[derive(Debug, PartialEq, Eq)]
+enum ReadyOutcome {
+    Processed,
+    Failed,
+    Skipped,
+}
 
-                match wait_for_file_stability(&path) {
-                    Ok(_) => {
-                        info!("File {} appears stable. Proceeding with processing.", path.display());
-                        match process_file(&path, destination, use_modified, use_camera_model, camera_model_is_prefix, manual_camera_model, copy_files, keep_names) {
-                            Ok(_) => {
-                                info!("Successfully processed {}", path.display());
-                            },
-                            Err(e) => warn!("Failed to process stable file {}: {}", path.display(), e),
-                        }
-                    }
-                    Err(e) => {
-                        warn!("File {} did not stabilise or error during check: {}. Skipping processing.", path.display(), e);
-                    }
+/// Waits for a file to be ready and sorts it. A sentinel file stands for the file
+/// it marks as complete.
+fn process_when_ready(path: &Path, options: &ProcessOptions) -> ReadyOutcome {
+    let path = &options.readiness.file_to_process(path);
+    if !path.is_file() {
+        debug!("File {} no longer exists. Skipping processing.", path.display());
+        return ReadyOutcome::Skipped;
+    }
+    if options.retry_queue.as_ref().is_some_and(|queue| queue.is_waiting(path)) {
+        debug!("Skipping {} until its next retry", path.display());
+        return ReadyOutcome::Skipped;
+    }
+    if !needs_processing(path, options) {
+        debug!("Skipping {}, which was already copied or is not media", path.display());
+        return ReadyOutcome::Skipped;
+    }
+    match options.readiness.wait(path) {
+        Ok(Ready::Yes) => {
+            info!(event = "ready", path:% = path.display(); "File {} is ready. Proceeding with processing.", path.display());
+            match process_file(path, options, None, None) {
+                Ok(_) => {
+                    info!("Successfully processed {}", path.display());
+                    options.readiness.finish(path, !options.copy_files);
+                    ReadyOutcome::Processed
+                },
+                Err(e) => {
+                    options.record_failure(path, &e);
+                    ReadyOutcome::Failed
                 }
-            } else {
-                debug!("FS Event for non-file path: {}. Ignoring for file processing.", path.display());
             }
         }
+        Ok(Ready::NotYet(reason)) => {
+            debug!("File {} is not ready ({}). Skipping for now.", path.display(), reason);
+            ReadyOutcome::Skipped
+        }
+        Err(e) => {
+            warn!("File {} did not become ready or error during check: {}. Skipping processing.", path.display(), e);
+            ReadyOutcome::Skipped
+        }
     }
-    delete_empty_folders(source)?;
-    Ok(())
 }
 
-fn process_file(file_path: &Path, destination: &str, use_modified: bool, use_camera_model: bool, camera_model_is_prefix: bool, manual_camera_model: Option<&String>, copy_files: bool, keep_names: bool) -> Result<()> {
-    let mut dest_path_option: Option<PathBuf> = None;
-
-    let is_media_file = if let Some(ext) = file_path.extension().and_then(OsStr::to_str) {
+fn is_media_file(file_path: &Path) -> bool {
+    if let Some(ext) = file_path.extension().and_then(OsStr::to_str) {
+        if raw::is_raw_extension(ext) {
+            return true;
+        }
         let mime_type = mime_guess::from_ext(ext).first_or_octet_stream();
         mime_type.type_() == mime::IMAGE || mime_type.type_() == mime::VIDEO
     } else {
         false
-    };
+    }
+}
+
+/// Applies the junk policy to a junk file found in the source. Copy mode never
+/// deletes anything, so there `delete` leaves the file in place.
+fn handle_junk_file(file_path: &Path, policy: JunkPolicy, options: &ProcessOptions) -> Result<(), SortError> {
+    if policy != JunkPolicy::Delete || options.copy_files {
+        debug!("Skipping junk file {}", file_path.display());
+        return Ok(());
+    }
+    fs::remove_file(file_path).map_err(|e| SortError::io(file_path, e))?;
+    info!(event = "deleted_junk", path:% = file_path.display(); "Deleting junk: {}", file_path.display());
+    if let Some(report) = &options.report {
+        report.record(&DeletedRecord { action: "deleted_junk", source: file_path });
+    }
+    if let Some(cleanup) = &options.folder_cleanup {
+        cleanup.note_processed(file_path);
+    }
+    Ok(())
+}
+
+/// Sorts a single file. `event` replaces the year, month and day folders when the file
+/// belongs to an event found by `events::group_events`, and `metadata` is passed when
+/// the file's metadata was already read for grouping.
+fn process_file(file_path: &Path, options: &ProcessOptions, event: Option<&EventFolder>, metadata: Option<MediaMetadata>) -> Result<(), SortError> {
+    let mut dest_path_option: Option<PathBuf> = None;
+    let mut record: Option<FileRecord> = None;
+
+    if is_state_file(file_path) {
+        debug!("Skipping ShutterSort state file {}", file_path.display());
+        return Ok(());
+    }
+    if options.readiness.is_sentinel(file_path) {
+        debug!("Skipping sentinel file {}", file_path.display());
+        return Ok(());
+    }
+    if let Some(policy) = file_path.file_name().and_then(|name| options.junk.policy_for(&name.to_string_lossy())) {
+        handle_junk_file(file_path, policy, options)?;
+        return Ok(());
+    }
+    if xmp::is_sidecar(file_path) {
+        if !file_path.exists() {
+            debug!("Sidecar {} was already sorted with its media file", file_path.display());
+            return Ok(());
+        }
+        if xmp::media_candidates(file_path).iter().any(|media| is_media_file(media)) {
+            debug!("Leaving sidecar {} to be sorted with its media file", file_path.display());
+            return Ok(());
+        }
+    }
+
+    let signature = if options.copy_files { FileSignature::read(file_path) } else { None };
+    if let Some(signature) = signature
+        && options.already_copied(file_path, &signature)
+    {
+        debug!("Skipping {} (already copied and unchanged)", file_path.display());
+        return Ok(());
+    }
 
-    if is_media_file {
+    let media = if is_media_file(file_path) {
         debug!("Processing media file: {}", file_path.display());
-        let date_time = extract_date(file_path, use_modified)
-            .context(format!("Failed to extract date from {}", file_path.display()))?;
+        Some(metadata.unwrap_or_else(|| MediaMetadata::read(file_path, options)))
+    } else {
+        None
+    };
 
-        let camera_model_str = if let Some(manual_model) = manual_camera_model {
-            manual_model.clone()
-        } else if use_camera_model {
-            extract_camera_model(file_path).unwrap_or_else(|_| "Unknown".to_string())
-        } else {
-            String::new()
-        };
-        dest_path_option = Some(create_destination_path(destination, &date_time, &camera_model_str, file_path, keep_names, camera_model_is_prefix)?);
+    if let Some(MediaMetadata { date: Ok((date_time, date_source)), camera_model: camera_model_str, tokens, location, .. }) = media {
+        info!(
+            event = "dated", path:% = file_path.display(), date_source:% = date_source, date:% = date_time;
+            "Dated {} from {}: {}", file_path.display(), date_source, date_time
+        );
+
+        let dest_path = create_destination_path(options, &date_time, &camera_model_str, &tokens, location.as_ref(), event, file_path)?;
+        record = Some(FileRecord {
+            source: file_path.to_path_buf(),
+            destination: dest_path.clone(),
+            action: if options.copy_files { "copied" } else { "moved" },
+            date: Some(date_time),
+            date_source: Some(date_source.to_string()),
+            camera_model: Some(camera_model_str).filter(|model| !model.is_empty()),
+            location,
+            metadata: options.needs_metadata_tokens().then_some(tokens),
+        });
+        dest_path_option = Some(dest_path);
+    } else if let Some(MediaMetadata { date: Err(e), .. }) = media {
+        warn!(
+            event = "undated", path:% = file_path.display(), message:% = e;
+            "No plausible date for {}: {}. Sorting into {}.", file_path.display(), e, options.undated_folder
+        );
+        let dest_path = get_undated_destination_path(options, file_path)?;
+        let action = if options.copy_files { "copied" } else { "moved" };
+        record = Some(FileRecord::undated(file_path, &dest_path, action));
+        dest_path_option = Some(dest_path);
     } else {
         debug!("File is not a media file (or has no/invalid extension): {}", file_path.display());
-        if !copy_files {
+        if !options.copy_files {
             // Only move non-media files if in move mode
-            dest_path_option = Some(get_unknown_destination_path(destination, file_path));
-            debug!("Non-media file will be moved to: {}", dest_path_option.as_ref().unwrap().display());
+            let dest_path = get_unknown_destination_path(options, file_path)?;
+            debug!("Non-media file will be moved to: {}", dest_path.display());
+            record = Some(FileRecord::undated(file_path, &dest_path, "moved"));
+            dest_path_option = Some(dest_path);
         } else {
             debug!("Skipping non-media file (copy mode enabled): {}", file_path.display());
         }
@@ -281,15 +1150,38 @@ fn process_file(file_path: &Path, destination: &str, use_modified: bool, use_cam
 
     if let Some(final_dest_path) = dest_path_option {
         if let Some(parent) = final_dest_path.parent() {
-            fs::create_dir_all(parent)?;
+            fs::create_dir_all(parent).map_err(|e| SortError::io(parent, e))?;
         }
 
-        if copy_files {
-            info!("Copying file {} to {}", file_path.display(), final_dest_path.display());
-            fs::copy(file_path, &final_dest_path)?;
+        info!(
+            event = "destination", path:% = file_path.display(), destination:% = final_dest_path.display();
+            "Destination for {}: {}", file_path.display(), final_dest_path.display()
+        );
+        let action = if options.copy_files {
+            copy_no_clobber(file_path, &final_dest_path)?;
+            if let Some(signature) = signature {
+                options.mark_copied(file_path, signature);
+            }
+            "copied"
         } else {
-            info!("Moving file {} to {}", file_path.display(), final_dest_path.display());
-            fs::rename(file_path, &final_dest_path)?;
+            fs::rename(file_path, &final_dest_path).map_err(|e| SortError::io(file_path, e))?;
+            "moved"
+        };
+        info!(
+            event = "transferred", path:% = file_path.display(), destination:% = final_dest_path.display(), action = action;
+            "{} file {} to {}", if options.copy_files { "Copied" } else { "Moved" }, file_path.display(), final_dest_path.display()
+        );
+        if is_media_file(file_path) {
+            transfer_sidecars(file_path, &final_dest_path, options);
+        }
+        if let Some(cleanup) = &options.folder_cleanup {
+            cleanup.note_processed(file_path);
+        }
+        if let (Some(report), Some(record)) = (&options.report, &record) {
+            report.record(record);
+        }
+        if let Some(queue) = &options.retry_queue {
+            queue.resolve(file_path);
         }
     } else {
         info!("Skipping file {} (no destination path determined, likely a non-media file in copy mode)", file_path.display());
@@ -298,125 +1190,426 @@ fn process_file(file_path: &Path, destination: &str, use_modified: bool, use_cam
     Ok(())
 }
 
-fn delete_empty_folders(source: &str) -> Result<()> {
-    let source_path = Path::new(source);
-
-    for entry in WalkDir::new(source_path)
-        .contents_first(true) 
-        .into_iter()
-        .filter_map(|e| e.ok()) 
-    {
-        let path = entry.path();
-
-        if path.is_dir() && path != source_path {
-            match fs::read_dir(path) {
-                Ok(mut dir_contents) => {
-                    if dir_contents.next().is_none() {
-                        match fs::remove_dir(path) {
-                            Ok(_) => {
-                                info!("Deleting empty folder: {}", path.display());
-                            }
-                            Err(e) => {
-                                warn!("Failed to delete folder {}: {}. It might already be deleted or access is denied.", path.display(), e);
-                            }
-                        }
-                    }
-                }
-                Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
-                    debug!("Directory {} not found when checking if empty, likely already deleted by a previous step.", path.display());
-                }
-                Err(e) => {
-                    warn!("Could not read directory {} to check if empty: {}", path.display(), e);
+/// Moves or copies a media file's XMP sidecars next to its destination, so edits
+/// stay with the image. Failures are logged without failing the media file.
+fn transfer_sidecars(file_path: &Path, dest_path: &Path, options: &ProcessOptions) {
+    for (sidecar, target) in xmp::sidecars_for(file_path, dest_path) {
+        if path_taken(&target, options.case_insensitive_names) {
+            warn!("Leaving sidecar {} in place, as {} already exists", sidecar.display(), target.display());
+            continue;
+        }
+        let action = if options.copy_files { "copied" } else { "moved" };
+        let result = if options.copy_files {
+            copy_no_clobber(&sidecar, &target)
+        } else {
+            fs::rename(&sidecar, &target).map_err(|e| SortError::io(&sidecar, e))
+        };
+        match result {
+            Ok(()) => {
+                info!(
+                    event = "transferred", path:% = sidecar.display(), destination:% = target.display(), action = action;
+                    "{} sidecar {} to {}", if options.copy_files { "Copied" } else { "Moved" }, sidecar.display(), target.display()
+                );
+                if let Some(report) = &options.report {
+                    report.record(&FileRecord::undated(&sidecar, &target, action));
                 }
             }
+            Err(e) => warn!("Failed to transfer sidecar {}: {}", sidecar.display(), e),
         }
     }
-    Ok(())
 }
 
-fn extract_date(file_path: &Path, use_modified: bool) -> Result<DateTime<Utc>> {
-    match extract_exif_date(file_path) {
-        Ok(datetime) => {
-            debug!("Successfully extracted EXIF date for {}: {:?}", file_path.display(), datetime);
-            return Ok(datetime);
+/// Copies a file without ever replacing an existing one. The destination is
+/// created exclusively, so a file that appeared since the uniqueness check, or
+/// one whose name only differs in case on a case-insensitive filesystem, makes
+/// the copy fail instead of being overwritten.
+fn copy_no_clobber(source: &Path, destination: &Path) -> Result<(), SortError> {
+    let mut reader = File::open(source).map_err(|e| SortError::io(source, e))?;
+    let mut writer = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(destination)
+        .map_err(|e| SortError::io(destination, e))?;
+    if let Err(e) = io::copy(&mut reader, &mut writer) {
+        drop(writer);
+        let _ = fs::remove_file(destination);
+        return Err(SortError::io(destination, e));
+    }
+    drop(writer);
+    let permissions = fs::metadata(source).map_err(|e| SortError::io(source, e))?.permissions();
+    fs::set_permissions(destination, permissions).map_err(|e| SortError::io(destination, e))
+}
+
+/// Handles a file that has used up its retries. When moving, it goes to the failed
+/// folder in the destination; in copy mode the source is left alone and the file
+/// stays listed in the retry queue file.
+fn quarantine_file(file_path: &Path, options: &ProcessOptions) {
+    let Some(queue) = &options.retry_queue else {
+        return;
+    };
+    if options.copy_files {
+        error!("Giving up on {}. It is listed in {}.", file_path.display(), queue.file().display());
+        return;
+    }
+    let Some(file_name) = file_path.file_name() else {
+        return;
+    };
+    let failed_path = Path::new(&options.destination).join(options.sanitizer.folder(&options.failed_folder));
+    let dest_path = ensure_unique_filepath(failed_path.join(options.sanitizer.file_name(&file_name.to_string_lossy())), &options.sanitizer, options.case_insensitive_names);
+    let result = fs::create_dir_all(&failed_path).and_then(|_| fs::rename(file_path, &dest_path));
+    match result {
+        Ok(()) => {
+            error!(
+                event = "quarantined", path:% = file_path.display(), destination:% = dest_path.display();
+                "Giving up on {}. Moved it to {}.", file_path.display(), dest_path.display()
+            );
+            if let Some(report) = &options.report {
+                report.record(&FileRecord::undated(file_path, &dest_path, "quarantined"));
+            }
+            queue.forget(file_path);
         }
-        Err(e) => {
-            debug!("Failed to extract EXIF date for {}: {}. Falling back to file metadata.", file_path.display(), e);
+        Err(e) => error!("Giving up on {}, and could not move it to {}: {}", file_path.display(), dest_path.display(), e),
+    }
+}
+
+/// Tries files from the retry queue whose next attempt is due.
+fn retry_due_files(source: &str, options: &ProcessOptions) -> Result<()> {
+    let Some(queue) = &options.retry_queue else {
+        return Ok(());
+    };
+    let due = queue.due(Utc::now());
+    if due.is_empty() {
+        return Ok(());
+    }
+    for path in due {
+        if options.should_stop() {
+            break;
+        }
+        if !path.is_file() {
+            debug!("Dropping retry for {}, which no longer exists", path.display());
+            queue.resolve(&path);
+            continue;
+        }
+        info!("Retrying {}", path.display());
+        match process_file(&path, options, None, None) {
+            Ok(_) => info!("Successfully processed {} on retry", path.display()),
+            Err(e) => options.record_failure(&path, &e),
         }
     }
+    delete_empty_folders(source, options)
+}
+
+/// A report line for a file that could not be sorted.
+#[derive(Debug, Serialize)]
+struct FailureRecord<'a> {
+    source: &'a Path,
+    action: &'static str,
+    error: &'static str,
+    message: String,
+}
+
+/// A report line describing where a file went and how it was dated.
+#[derive(Debug, Serialize)]
+struct FileRecord {
+    source: PathBuf,
+    destination: PathBuf,
+    action: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date_source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    camera_model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    location: Option<Location>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<MetadataTokens>,
+}
 
-    match extract_video_date(file_path) {
-        Ok(datetime) => {
-            debug!("Successfully extracted video date for {}: {:?}", file_path.display(), datetime);
-            return Ok(datetime);
+impl FileRecord {
+    fn undated(file_path: &Path, dest_path: &Path, action: &'static str) -> Self {
+        FileRecord {
+            source: file_path.to_path_buf(),
+            destination: dest_path.to_path_buf(),
+            action,
+            date: None,
+            date_source: None,
+            camera_model: None,
+            location: None,
+            metadata: None,
         }
-        Err(e) => {
-            debug!("Failed to extract video date for {}: {}. Falling back to file metadata.", file_path.display(), e);
+    }
+}
+
+/// A report line written when a run starts, recording whether the source may be
+/// modified. Copy mode without `cleanup_empty_folders` never touches the source.
+#[derive(Debug, Serialize)]
+struct RunRecord {
+    action: &'static str,
+    source: PathBuf,
+    destination: PathBuf,
+    mode: &'static str,
+    cleanup_empty_folders: bool,
+}
+
+/// Deletes source folders emptied by this run, when cleanup is enabled.
+fn delete_empty_folders(source: &str, options: &ProcessOptions) -> Result<()> {
+    match &options.folder_cleanup {
+        Some(cleanup) => cleanup.run(Path::new(source), options.report.as_ref()),
+        None => debug!("Leaving empty folders in {} untouched", source),
+    }
+    Ok(())
+}
+
+/// Tries each configured date source in order and returns the first date found,
+/// along with the source it came from.
+fn extract_date(file_path: &Path, exif: &LazyExif, options: &ProcessOptions) -> Result<(DateTime<Utc>, DateSource)> {
+    for &configured_source in &options.date_sources {
+        let mut source = configured_source;
+        let result = match source {
+            DateSource::Exif => extract_exif_date(exif),
+            DateSource::Video => extract_video_date(file_path),
+            DateSource::Xmp => xmp::extract_xmp_date(file_path),
+            DateSource::Filename => options.filename_dates.parse(file_path, &options.source),
+            DateSource::Created => match extract_created_date(file_path) {
+                Ok(datetime) => Ok(datetime),
+                Err(e) => {
+                    if !BIRTH_TIME_FALLBACK_LOGGED.swap(true, Ordering::Relaxed) {
+                        warn!("File creation time is unavailable ({:#}). Falling back to modified time for such files.", e);
+                    }
+                    debug!("No creation time for {}, using modified time instead", file_path.display());
+                    source = DateSource::Modified;
+                    extract_modified_date(file_path)
+                }
+            },
+            DateSource::Modified => extract_modified_date(file_path),
+        };
+        match result {
+            Ok(mut datetime) => {
+                debug!("Successfully extracted {} date for {}: {:?}", source, file_path.display(), datetime);
+                if let Some(clock_offsets) = &options.clock_offsets
+                    && !source.is_file_timestamp()
+                    && let Some(offset) = clock_offsets.offset_for(&camera_identifiers(exif, options), datetime)
+                {
+                    let Some(corrected) = datetime.checked_add_signed(offset) else {
+                        warn!("Clock offset {} moves the {} date {} of {} out of range", clock::format_offset(offset), source, datetime, file_path.display());
+                        continue;
+                    };
+                    info!("Corrected camera clock for {} by {}: {} -> {}", file_path.display(), clock::format_offset(offset), datetime, corrected);
+                    datetime = corrected;
+                }
+                match options.date_bounds.check(datetime) {
+                    Ok(()) => return Ok((datetime, source)),
+                    Err(e) => info!("Ignoring {} date for {}: {}", source, file_path.display(), e),
+                }
+            }
+            Err(e) => {
+                debug!("Failed to extract {} date for {}: {}", source, file_path.display(), e);
+            }
         }
     }
+    if options.date_sources.iter().any(|source| source.is_file_timestamp()) {
+        anyhow::bail!("no date source gave a plausible date")
+    } else {
+        anyhow::bail!("no plausible date in metadata or name, and file timestamps are not allowed")
+    }
+}
+
+/// Names a file's camera may be listed under in the clock offsets file.
+fn camera_identifiers(exif: &LazyExif, options: &ProcessOptions) -> Vec<String> {
+    let mut identifiers = Vec::new();
+    if let Ok(exif) = exif.get() {
+        for tag in [exif::Tag::BodySerialNumber, exif::Tag::Model, exif::Tag::Make] {
+            if let Some(value) = exif_ascii(exif, tag) {
+                identifiers.push(value);
+            }
+        }
+    }
+    if let Some(manual_model) = &options.manual_camera_model {
+        identifiers.push(manual_model.clone());
+    }
+    identifiers
+}
+
+/// Prints the clock offset entry that makes `target` agree with `reference`.
+fn print_clock_offset(reference: &Path, target: &Path) -> Result<()> {
+    let capture_time = |path: &Path| {
+        extract_exif_date(&LazyExif::new(path))
+            .or_else(|_| extract_video_date(path))
+            .with_context(|| format!("Failed to read capture time from {}", path.display()))
+    };
+    let reference_time = capture_time(reference)?;
+    let target_time = capture_time(target)?;
+    let offset = reference_time - target_time;
+    let camera = extract_camera_model(&LazyExif::new(target)).unwrap_or_else(|_| "Unknown".to_string());
+
+    println!("Reference: {} ({})", reference_time, reference.display());
+    println!("Target:    {} ({})", target_time, target.display());
+    println!("Add this line to your clock offsets file:");
+    println!("{} {}", camera, clock::format_offset(offset));
+    Ok(())
+}
+
+/// Set once the fallback from creation time to modified time has been logged, so
+/// a filesystem without birth times does not produce a warning per file.
+static BIRTH_TIME_FALLBACK_LOGGED: AtomicBool = AtomicBool::new(false);
+
+fn extract_created_date(file_path: &Path) -> Result<DateTime<Utc>> {
+    let created_time = birthtime::birth_time(file_path)
+        .with_context(|| format!("Failed to get creation time for {}", file_path.display()))?;
+    Ok(created_time.into())
+}
 
-    debug!("Attempting to use file metadata for {}", file_path.display());
+fn extract_modified_date(file_path: &Path) -> Result<DateTime<Utc>> {
     let metadata = fs::metadata(file_path)
         .with_context(|| format!("Failed to read metadata for {}", file_path.display()))?;
+    let modified_time = metadata.modified()
+        .with_context(|| format!("Failed to get modified time for {}", file_path.display()))?;
+    Ok(modified_time.into())
+}
 
-    if use_modified {
-        debug!("Using modified time for {}", file_path.display());
-        let modified_time = metadata.modified()
-            .with_context(|| format!("Failed to get modified time for {}", file_path.display()))?;
-        let datetime: DateTime<Utc> = modified_time.into();
-        Ok(datetime)
-    } else {
-        debug!("Using created time for {}", file_path.display());
-        let created_time = metadata.created()
-            .with_context(|| format!("Failed to get creation time for {}", file_path.display()))?;
-        let datetime: DateTime<Utc> = created_time.into();
-        Ok(datetime)
+/// EXIF fields read from a file. Most containers hold a single EXIF block, but some
+/// RAW formats split their metadata across several.
+struct ExifData {
+    blocks: Vec<exif::Exif>,
+}
+
+impl ExifData {
+    fn get_field(&self, tag: exif::Tag, ifd_num: exif::In) -> Option<&exif::Field> {
+        self.blocks.iter().find_map(|block| block.get_field(tag, ifd_num))
+    }
+}
+
+/// A file's EXIF data, read on first use and shared by every helper that needs it,
+/// so large RAW files are read once per file rather than once per lookup.
+/// What a media file's metadata contributes to its destination.
+struct MediaMetadata {
+    date: Result<(DateTime<Utc>, DateSource)>,
+    camera_model: String,
+    tokens: MetadataTokens,
+    location: Option<Location>,
+    /// Only read when events are split by distance.
+    coordinates: Option<(f64, f64)>,
+}
+
+impl MediaMetadata {
+    /// Reads the date and, for dated files, the camera model, metadata tokens and
+    /// location options ask for, reading the file's EXIF at most once.
+    fn read(file_path: &Path, options: &ProcessOptions) -> Self {
+        let exif = LazyExif::new(file_path);
+        let date = extract_date(file_path, &exif, options);
+        if date.is_err() {
+            return MediaMetadata { date, camera_model: String::new(), tokens: MetadataTokens::default(), location: None, coordinates: None };
+        }
+
+        let camera_model = if let Some(manual_model) = &options.manual_camera_model {
+            manual_model.clone()
+        } else if options.use_camera_model {
+            extract_camera_model(&exif).unwrap_or_else(|_| "Unknown".to_string())
+        } else {
+            String::new()
+        };
+
+        let tokens = if options.needs_metadata_tokens() {
+            let tokens = MetadataTokens::read(&exif);
+            debug!("Metadata tokens for {}: {:?}", file_path.display(), tokens);
+            tokens
+        } else {
+            MetadataTokens::default()
+        };
+
+        let location = match &options.geocoder {
+            Some(geocoder) => resolve_location(geocoder, &exif),
+            None => None,
+        };
+        let coordinates = if options.event_grouping.as_ref().is_some_and(|grouping| grouping.split_distance_km.is_some()) {
+            exif.get().and_then(extract_gps_coordinates).ok()
+        } else {
+            None
+        };
+        MediaMetadata { date, camera_model, tokens, location, coordinates }
+    }
+}
+
+struct LazyExif<'a> {
+    path: &'a Path,
+    data: OnceCell<Result<ExifData, String>>,
+}
+
+impl<'a> LazyExif<'a> {
+    fn new(path: &'a Path) -> Self {
+        LazyExif { path, data: OnceCell::new() }
+    }
+
+    fn get(&self) -> Result<&ExifData> {
+        self.data
+            .get_or_init(|| read_exif(self.path).map_err(|e| format!("{:#}", e)))
+            .as_ref()
+            .map_err(|e| anyhow::anyhow!("{}", e))
     }
 }
 
-fn extract_exif_date(file_path: &Path) -> Result<DateTime<Utc>> {
+fn read_exif(file_path: &Path) -> Result<ExifData> {
+    let ext = file_path.extension().and_then(OsStr::to_str).unwrap_or("");
+    if raw::is_raw_extension(ext) {
+        let blocks = match std::panic::catch_unwind(|| raw::read_raw_exif(file_path)) {
+            Ok(result) => result.context(format!("EXIF: Failed to read RAW metadata from {}", file_path.display()))?,
+            Err(_panic_payload) => anyhow::bail!("Panic occurred while trying to read RAW metadata from {}", file_path.display()),
+        };
+        return Ok(ExifData { blocks });
+    }
+
     let file = File::open(file_path).context(format!("EXIF: Failed to open file {}", file_path.display()))?;
     let mut bufreader = BufReader::new(&file);
     let exifreader = exif::Reader::new();
     let exif = exifreader.read_from_container(&mut bufreader).context(format!("EXIF: Failed to read container from {}", file_path.display()))?;
+    Ok(ExifData { blocks: vec![exif] })
+}
+
+fn extract_exif_date(exif: &LazyExif) -> Result<DateTime<Utc>> {
+    let file_path = exif.path;
+    let exif = exif.get()?;
 
     for &tag in &[
         exif::Tag::DateTimeOriginal,
         exif::Tag::DateTime,
         exif::Tag::DateTimeDigitized,
     ] {
-        if let Some(field) = exif.get_field(tag, exif::In::PRIMARY) {
-            if let exif::Value::Ascii(ref vec) = field.value {
-                if !vec.is_empty() {
-                    if let Ok(s) = std::str::from_utf8(&vec[0]) {
-                        if s.len() >= 19 {
-                            let year: i32 = s[0..4].parse()?;
-                            let month: u32 = s[5..7].parse()?;
-                            let day: u32 = s[8..10].parse()?;
-                            let hour: u32 = s[11..13].parse()?;
-                            let minute: u32 = s[14..16].parse()?;
-                            let second: u32 = s[17..19].parse()?;
-                            return Utc.with_ymd_and_hms(year, month, day, hour, minute, second)
-                                .single()
-                                .ok_or_else(|| anyhow::anyhow!(
-                                    "EXIF: Failed to create unambiguous DateTime for {} (date/time: {}-{}-{} {}:{}:{} might be invalid or ambiguous)", 
-                                    file_path.display(), year, month, day, hour, minute, second
-                                ));
-                        }
-                    }
-                }
-            }
+        if let Some(s) = exif_ascii(exif, tag)
+            && s.len() >= 19
+        {
+            let year: i32 = s[0..4].parse()?;
+            let month: u32 = s[5..7].parse()?;
+            let day: u32 = s[8..10].parse()?;
+            let hour: u32 = s[11..13].parse()?;
+            let minute: u32 = s[14..16].parse()?;
+            let second: u32 = s[17..19].parse()?;
+            return Utc.with_ymd_and_hms(year, month, day, hour, minute, second)
+                .single()
+                .ok_or_else(|| anyhow::anyhow!(
+                    "EXIF: Failed to create unambiguous DateTime for {} (date/time: {}-{}-{} {}:{}:{} might be invalid or ambiguous)",
+                    file_path.display(), year, month, day, hour, minute, second
+                ));
         }
     }
     anyhow::bail!("EXIF: No date found in EXIF data for {}", file_path.display())
 }
 
 fn extract_video_date(file_path: &Path) -> Result<DateTime<Utc>> {
-    debug!("Attempting to extract QuickTime video date using mediameta for {}", file_path.display());
-
     let ext = file_path.extension().and_then(OsStr::to_str).unwrap_or("").to_lowercase();
+    if matches!(ext.as_str(), "mkv" | "webm" | "avi" | "mts" | "m2ts" | "3gp" | "3g2") {
+        debug!("Attempting to extract {} video date for {}", ext, file_path.display());
+        return match std::panic::catch_unwind(|| video::extract_container_date(file_path, &ext)) {
+            Ok(result) => result,
+            Err(_panic_payload) => {
+                anyhow::bail!("Panic occurred while trying to read {} container date for {}", ext, file_path.display())
+            }
+        };
+    }
+
+    debug!("Attempting to extract QuickTime video date using mediameta for {}", file_path.display());
     if !matches!(ext.as_str(), "mp4" | "mov" | "m4v" | "qt") {
-        anyhow::bail!("Not a supported video file type for date extraction with mediameta: {}", file_path.display());
+        anyhow::bail!("Not a supported video file type for date extraction: {}", file_path.display());
     }
 
     let result = std::panic::catch_unwind(|| {
@@ -442,61 +1635,263 @@ fn extract_video_date(file_path: &Path) -> Result<DateTime<Utc>> {
     }
 }
 
-fn extract_camera_model(file_path: &Path) -> Result<String> {
-    let file = File::open(file_path)?;
-    let mut bufreader = BufReader::new(&file);
-    let exifreader = exif::Reader::new();
-    let exif = exifreader.read_from_container(&mut bufreader)?;
-    if let Some(field) = exif.get_field(exif::Tag::Model, exif::In::PRIMARY) {
-        if let exif::Value::Ascii(ref vec) = field.value {
-            if !vec.is_empty() {
-                if let Ok(s) = std::str::from_utf8(&vec[0]) {
-                    let model = s.trim().replace(char::is_whitespace, "_");
-                    return Ok(model);
-                }
+fn extract_camera_model(exif: &LazyExif) -> Result<String> {
+    let exif = exif.get()?;
+    if let Some(s) = exif_ascii(exif, exif::Tag::Model) {
+        let model = s.trim().replace(char::is_whitespace, "_");
+        return Ok(model);
+    }
+    if let Some(s) = exif_ascii(exif, exif::Tag::Make) {
+        let make = s.trim().replace(char::is_whitespace, "_");
+        return Ok(make);
+    }
+    anyhow::bail!("No camera model found in EXIF data")
+}
+
+/// Returns the first string of an ASCII field in the primary IFD. Bytes that are
+/// not valid UTF-8 are replaced rather than discarding the whole value.
+fn exif_ascii(exif: &ExifData, tag: exif::Tag) -> Option<String> {
+    let field = exif.get_field(tag, exif::In::PRIMARY)?;
+    if let exif::Value::Ascii(ref vec) = field.value
+        && let Some(first) = vec.first()
+    {
+        return Some(String::from_utf8_lossy(first).trim_matches('\0').to_string());
+    }
+    None
+}
+
+fn extract_lens_model(exif: &ExifData) -> Result<String> {
+    match exif_ascii(exif, exif::Tag::LensModel).as_deref().map(str::trim) {
+        Some(lens) if !lens.is_empty() => Ok(lens.to_string()),
+        _ => anyhow::bail!("No lens model found in EXIF data"),
+    }
+}
+
+fn extract_focal_length(exif: &ExifData) -> Result<f64> {
+    let field = exif.get_field(exif::Tag::FocalLength, exif::In::PRIMARY)
+        .ok_or_else(|| anyhow::anyhow!("No focal length found in EXIF data"))?;
+    match field.value {
+        exif::Value::Rational(ref vec) if !vec.is_empty() && vec[0].denom != 0 => Ok(vec[0].to_f64()),
+        _ => anyhow::bail!("Focal length has an unexpected EXIF type"),
+    }
+}
+
+fn extract_iso(exif: &ExifData) -> Result<u32> {
+    exif.get_field(exif::Tag::PhotographicSensitivity, exif::In::PRIMARY)
+        .and_then(|field| field.value.get_uint(0))
+        .ok_or_else(|| anyhow::anyhow!("No ISO found in EXIF data"))
+}
+
+fn extract_exposure_program(exif: &ExifData) -> Result<String> {
+    let value = exif.get_field(exif::Tag::ExposureProgram, exif::In::PRIMARY)
+        .and_then(|field| field.value.get_uint(0))
+        .ok_or_else(|| anyhow::anyhow!("No exposure program found in EXIF data"))?;
+    let name = match value {
+        1 => "Manual",
+        2 => "Program",
+        3 => "AperturePriority",
+        4 => "ShutterPriority",
+        5 => "Creative",
+        6 => "Action",
+        7 => "Portrait",
+        8 => "Landscape",
+        _ => anyhow::bail!("Exposure program {} is not defined", value),
+    };
+    Ok(name.to_string())
+}
+
+fn extract_image_dimensions(exif: &ExifData) -> Result<(u32, u32)> {
+    let uint = |tag| exif.get_field(tag, exif::In::PRIMARY).and_then(|field| field.value.get_uint(0));
+    uint(exif::Tag::PixelXDimension)
+        .zip(uint(exif::Tag::PixelYDimension))
+        .or_else(|| uint(exif::Tag::ImageWidth).zip(uint(exif::Tag::ImageLength)))
+        .ok_or_else(|| anyhow::anyhow!("No image dimensions found in EXIF data"))
+}
+
+fn extract_orientation(exif: &ExifData) -> Result<String> {
+    let value = exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)
+        .and_then(|field| field.value.get_uint(0))
+        .ok_or_else(|| anyhow::anyhow!("No orientation found in EXIF data"))?;
+    let name = match value {
+        1 => "Horizontal",
+        2 => "MirrorHorizontal",
+        3 => "Rotate180",
+        4 => "MirrorVertical",
+        5 => "MirrorHorizontalRotate270",
+        6 => "Rotate90",
+        7 => "MirrorHorizontalRotate90",
+        8 => "Rotate270",
+        _ => anyhow::bail!("Orientation {} is not defined", value),
+    };
+    Ok(name.to_string())
+}
+
+fn extract_software(exif: &ExifData) -> Result<String> {
+    match exif_ascii(exif, exif::Tag::Software).as_deref().map(str::trim) {
+        Some(software) if !software.is_empty() => Ok(software.to_string()),
+        _ => anyhow::bail!("No software found in EXIF data"),
+    }
+}
+
+fn extract_gps_coordinates(exif: &ExifData) -> Result<(f64, f64)> {
+    let coordinate = |tag, ref_tag, negative_ref: &str| -> Result<f64> {
+        let field = exif.get_field(tag, exif::In::PRIMARY)
+            .ok_or_else(|| anyhow::anyhow!("No {} found in EXIF data", tag))?;
+        let degrees = match field.value {
+            exif::Value::Rational(ref dms) if dms.len() >= 3 && dms.iter().all(|r| r.denom != 0) => {
+                dms[0].to_f64() + dms[1].to_f64() / 60.0 + dms[2].to_f64() / 3600.0
+            }
+            _ => anyhow::bail!("{} has an unexpected EXIF type", tag),
+        };
+        match exif_ascii(exif, ref_tag) {
+            Some(r) if r.eq_ignore_ascii_case(negative_ref) => Ok(-degrees),
+            Some(_) => Ok(degrees),
+            None => anyhow::bail!("No {} found in EXIF data", ref_tag),
+        }
+    };
+    let latitude = coordinate(exif::Tag::GPSLatitude, exif::Tag::GPSLatitudeRef, "S")?;
+    let longitude = coordinate(exif::Tag::GPSLongitude, exif::Tag::GPSLongitudeRef, "W")?;
+    if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+        anyhow::bail!("GPS coordinates ({}, {}) are out of range", latitude, longitude);
+    }
+    Ok((latitude, longitude))
+}
+
+fn resolve_location(geocoder: &Geocoder, exif: &LazyExif) -> Option<Location> {
+    let file_path = exif.path;
+    let coordinates = exif.get().and_then(extract_gps_coordinates);
+    match coordinates {
+        Ok((latitude, longitude)) => {
+            let location = geocoder.lookup(latitude, longitude);
+            if location.is_none() {
+                debug!("No known place near ({}, {}) for {}", latitude, longitude, file_path.display());
+            }
+            location
+        }
+        Err(e) => {
+            debug!("No GPS coordinates for {}: {}", file_path.display(), e);
+            None
+        }
+    }
+}
+
+/// EXIF values read once per file for use as layout tokens.
+#[derive(Debug, Default, Clone, Serialize)]
+struct MetadataTokens {
+    lens_model: Option<String>,
+    focal_length: Option<f64>,
+    iso: Option<u32>,
+    exposure_program: Option<String>,
+    dimensions: Option<(u32, u32)>,
+    orientation: Option<String>,
+    software: Option<String>,
+}
+
+impl MetadataTokens {
+    fn read(exif: &LazyExif) -> Self {
+        match exif.get() {
+            Ok(data) => MetadataTokens {
+                lens_model: extract_lens_model(data).ok(),
+                focal_length: extract_focal_length(data).ok(),
+                iso: extract_iso(data).ok(),
+                exposure_program: extract_exposure_program(data).ok(),
+                dimensions: extract_image_dimensions(data).ok(),
+                orientation: extract_orientation(data).ok(),
+                software: extract_software(data).ok(),
+            },
+            Err(e) => {
+                debug!("No metadata tokens available for {}: {}", exif.path.display(), e);
+                MetadataTokens::default()
             }
         }
     }
-    if let Some(field) = exif.get_field(exif::Tag::Make, exif::In::PRIMARY) {
-        if let exif::Value::Ascii(ref vec) = field.value {
-            if !vec.is_empty() {
-                if let Ok(s) = std::str::from_utf8(&vec[0]) {
-                    let make = s.trim().replace(char::is_whitespace, "_");
-                    return Ok(make);
+
+    /// Formats a token for use in a folder or file name, e.g. `50mm` or `ISO3200`.
+    fn format(&self, token: MetadataToken) -> Option<String> {
+        let value = match token {
+            MetadataToken::Lens => self.lens_model.clone()?,
+            MetadataToken::FocalLength => {
+                let focal_length = self.focal_length?;
+                if focal_length.fract() == 0.0 {
+                    format!("{}mm", focal_length)
+                } else {
+                    format!("{:.1}mm", focal_length)
                 }
             }
+            MetadataToken::Iso => format!("ISO{}", self.iso?),
+            MetadataToken::ExposureProgram => self.exposure_program.clone()?,
+            MetadataToken::Dimensions => {
+                let (width, height) = self.dimensions?;
+                format!("{}x{}", width, height)
+            }
+            MetadataToken::Orientation => self.orientation.clone()?,
+            MetadataToken::Software => self.software.clone()?,
+        };
+        Some(path_token(&value))
+    }
+}
+
+/// Formats a metadata value like camera models are, with underscores for spaces.
+/// Anything else unsafe in a path is handled by the `Sanitizer`.
+fn path_token(value: &str) -> String {
+    value.trim().replace(char::is_whitespace, "_")
+}
+
+/// Extension spellings that are folded into one canonical form.
+const EXTENSION_ALIASES: &[(&str, &str)] = &[("jpeg", "jpg"), ("jpe", "jpg"), ("tif", "tiff"), ("qt", "mov")];
+
+fn normalize_extension(extension: &str) -> String {
+    let lower = extension.to_lowercase();
+    EXTENSION_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == lower)
+        .map_or(lower, |(_, canonical)| canonical.to_string())
+}
+
+/// Replaces the extension of a file name when extensions are being normalised.
+fn file_name_with_extension(options: &ProcessOptions, file_name: &str) -> String {
+    match file_name.rsplit_once('.') {
+        Some((stem, ext)) if options.normalize_extensions && !stem.is_empty() => {
+            format!("{}.{}", stem, normalize_extension(ext))
         }
+        _ => file_name.to_string(),
     }
-    anyhow::bail!("No camera model found in EXIF data")
 }
 
-fn ensure_unique_filepath(path: PathBuf) -> PathBuf {
-    if !path.exists() {
+/// Whether a file already occupies the path. With `case_insensitive` set, a file
+/// whose name differs only in case also counts, since `IMG.JPG` and `IMG.jpg`
+/// would be the same file once copied to a case-insensitive filesystem.
+fn path_taken(path: &Path, case_insensitive: bool) -> bool {
+    if path.exists() {
+        return true;
+    }
+    if !case_insensitive {
+        return false;
+    }
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        return false;
+    };
+    let name = name.to_string_lossy().to_lowercase();
+    fs::read_dir(parent).is_ok_and(|entries| {
+        entries.flatten().any(|entry| entry.file_name().to_string_lossy().to_lowercase() == name)
+    })
+}
+
+fn ensure_unique_filepath(path: PathBuf, sanitizer: &Sanitizer, case_insensitive: bool) -> PathBuf {
+    if !path_taken(&path, case_insensitive) {
         debug!("Path {} is unique", path.display());
         return path;
     }
 
     let parent_dir = path.parent().unwrap_or_else(|| Path::new(""));
-    
-    let filename = path.file_stem()
-        .unwrap_or_else(|| OsStr::new("")) 
-        .to_str()
-        .unwrap_or("");
-
-    let extension = path.extension()
-        .unwrap_or_else(|| OsStr::new(""))
-        .to_str()
-        .unwrap_or("");
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
 
     let mut counter = 1;
     loop {
-        let new_filename = if extension.is_empty() {
-            format!("{}_{}", filename, counter)
-        } else {
-            format!("{}_{}.{}", filename, counter, extension)
-        };
+        // The suffix can push a name over the length limit, so the stem gives way.
+        let new_filename = sanitizer.suffixed_file_name(&file_name, &format!("_{}", counter));
         let candidate_path = parent_dir.join(new_filename);
-        if !candidate_path.exists() {
+        if !path_taken(&candidate_path, case_insensitive) {
             debug!("Saving file to {} as file with same name already exists.", candidate_path.display());
             return candidate_path;
         }
@@ -505,75 +1900,118 @@ fn ensure_unique_filepath(path: PathBuf) -> PathBuf {
 }
 
 fn create_destination_path(
-    destination: &str,
+    options: &ProcessOptions,
     date_time: &DateTime<Utc>,
     camera_model: &str,
+    tokens: &MetadataTokens,
+    location: Option<&Location>,
+    event: Option<&EventFolder>,
     file_path: &Path,
-    keep_names: bool,
-    camera_model_is_prefix: bool,
-) -> Result<PathBuf> {
+) -> Result<PathBuf, SortError> {
     let year_str = date_time.year().to_string();
     let month_str = format!("{:02}", date_time.month());
     let day_str = format!("{:02}", date_time.day());
 
-    let mut base_path = PathBuf::from(destination);
+    let mut base_path = PathBuf::from(&options.destination);
+    let sanitizer = &options.sanitizer;
+
+    if options.camera_model_is_prefix && !camera_model.is_empty() {
+        base_path.push(sanitizer.folder(camera_model));
+    }
+
+    match event {
+        Some(event) => {
+            base_path.push(event.year.to_string());
+            base_path.push(sanitizer.folder(&event.name));
+        }
+        None => {
+            base_path.push(year_str);
+            if options.date_folders >= DateFolders::Month {
+                base_path.push(month_str);
+            }
+            if options.date_folders >= DateFolders::Day {
+                base_path.push(day_str);
+            }
+        }
+    }
 
-    if camera_model_is_prefix && !camera_model.is_empty() {
-        base_path.push(camera_model);
+    if !options.location_levels.is_empty() {
+        match location {
+            Some(location) => {
+                for level in &options.location_levels {
+                    let name = match level {
+                        LocationLevel::Country => Some(&location.country),
+                        LocationLevel::Region => location.region.as_ref(),
+                        LocationLevel::City => Some(&location.city),
+                    };
+                    if let Some(name) = name {
+                        base_path.push(sanitizer.folder(&path_token(name)));
+                    }
+                }
+            }
+            None => base_path.push(sanitizer.folder(&options.location_placeholder)),
+        }
     }
 
-    base_path.push(year_str);
-    base_path.push(month_str);
-    base_path.push(day_str);
+    if !options.camera_model_is_prefix && !camera_model.is_empty() {
+        base_path.push(sanitizer.folder(camera_model));
+    }
 
-    if !camera_model_is_prefix && !camera_model.is_empty() {
-        base_path.push(camera_model);
+    for &token in &options.folder_tokens {
+        base_path.push(sanitizer.folder(&tokens.format(token).unwrap_or_else(|| "Unknown".to_string())));
     }
-    
+
     let dest_subfolder_path = base_path;
 
-    let initial_dest_path: PathBuf = if keep_names {
-        let original_filename_osstr = file_path.file_name().ok_or_else(|| anyhow::anyhow!("Invalid original filename"))?;
-        dest_subfolder_path.join(original_filename_osstr)
+    let initial_dest_path: PathBuf = if options.keep_names {
+        let original_filename_osstr = file_path.file_name().ok_or_else(|| SortError::metadata(file_path, "Invalid original filename"))?;
+        let file_name = file_name_with_extension(options, &original_filename_osstr.to_string_lossy());
+        dest_subfolder_path.join(sanitizer.file_name(&file_name))
     } else {
-        let timestamp_str = date_time.format("%Y-%m-%dT%H-%M-%S").to_string();
-        let file_ext_str = file_path
+        let mut timestamp_str = date_time.format("%Y-%m-%dT%H-%M-%S").to_string();
+        for &token in &options.filename_tokens {
+            if let Some(value) = tokens.format(token) {
+                timestamp_str.push('_');
+                timestamp_str.push_str(&value);
+            }
+        }
+        let mut file_ext_str = file_path
             .extension()
             .and_then(OsStr::to_str)
-            .unwrap_or("");
-        
+            .unwrap_or("")
+            .to_string();
+        if options.normalize_extensions {
+            file_ext_str = normalize_extension(&file_ext_str);
+        }
+
         let filename = if file_ext_str.is_empty() {
             timestamp_str
         } else {
             format!("{}.{}", timestamp_str, file_ext_str)
         };
-        dest_subfolder_path.join(&filename)
+        dest_subfolder_path.join(sanitizer.file_name(&filename))
     };
 
-    Ok(ensure_unique_filepath(initial_dest_path))
+    Ok(ensure_unique_filepath(initial_dest_path, &options.sanitizer, options.case_insensitive_names))
 }
 
-fn get_unknown_destination_path(destination: &str, file_path: &Path) -> PathBuf {
-    let unknown_path = Path::new(destination).join("unknown");
-    fs::create_dir_all(&unknown_path).unwrap();
-    let unknown_file_path = unknown_path.join(file_path.file_name().unwrap());
-    unknown_file_path
+fn get_unknown_destination_path(options: &ProcessOptions, file_path: &Path) -> Result<PathBuf, SortError> {
+    let unknown_path = Path::new(&options.destination).join("unknown");
+    let file_name = file_path.file_name().ok_or_else(|| SortError::metadata(file_path, "Invalid original filename"))?;
+    Ok(unknown_path.join(options.sanitizer.file_name(&file_name.to_string_lossy())))
 }
 
-fn poll_directory(
-    source: &str,
-    destination: &str,
-    use_modified: bool,
-    use_camera_model: bool,
-    camera_model_is_prefix: bool,
-    manual_camera_model: Option<&String>,
-    copy_files: bool,
-    keep_names: bool,
-    poll_interval_secs: u64,
-) -> Result<()> {
+/// Media files without a plausible date keep their name in the undated folder.
+fn get_undated_destination_path(options: &ProcessOptions, file_path: &Path) -> Result<PathBuf, SortError> {
+    let file_name = file_path.file_name().ok_or_else(|| SortError::metadata(file_path, "Invalid original filename"))?;
+    let undated_path = Path::new(&options.destination).join(options.sanitizer.folder(&options.undated_folder));
+    Ok(ensure_unique_filepath(undated_path.join(options.sanitizer.file_name(&file_name.to_string_lossy())), &options.sanitizer, options.case_insensitive_names))
+}
+
+fn poll_directory(source: &str, options: &ProcessOptions, poll_interval_secs: u64) -> Result<()> {
     info!(
         "Starting polling mode for directory: {}. Interval: {}s. Copy mode: {}, Keep names: {}",
-        source, poll_interval_secs, copy_files, keep_names
+        source, poll_interval_secs, options.copy_files, options.keep_names
     );
     let poll_duration = Duration::from_secs(poll_interval_secs);
     let source_path_obj = Path::new(source);
@@ -581,70 +2019,48 @@ fn poll_directory(
     loop {
         info!("Polling cycle started for source: {}", source);
         let mut files_found_in_cycle = 0;
-        let mut files_processed_successfully_in_cycle = 0;
-        let mut stability_checks_passed = 0;
-        let mut stability_checks_failed = 0;
+        let mut files_processed_in_cycle = 0;
+        let mut files_failed_in_cycle = 0;
+        let mut files_skipped_in_cycle = 0;
 
-        for entry_result in WalkDir::new(source_path_obj).into_iter() {
+        for entry_result in WalkDir::new(source_path_obj).into_iter().filter_entry(|e| !options.is_excluded(e.path())) {
             match entry_result {
                 Ok(entry) => {
                     if entry.file_type().is_file() {
                         let file_path = entry.path();
                         files_found_in_cycle += 1;
-                        debug!("Polling: Found file candidate: {}", file_path.display());
-
-                        info!("Polling: Checking stability for file: {}", file_path.display());
-                        match wait_for_file_stability(file_path) {
-                            Ok(_) => {
-                                stability_checks_passed += 1;
-                                info!("Polling: File {} appears stable. Proceeding with processing.", file_path.display());
-
-                                match process_file(
-                                    file_path,
-                                    destination,
-                                    use_modified,
-                                    use_camera_model,
-                                    camera_model_is_prefix,
-                                    manual_camera_model,
-                                    copy_files,
-                                    keep_names,
-                                ) {
-                                    Ok(_) => {
-                                        info!("Polling: Successfully processed file {}", file_path.display());
-                                        files_processed_successfully_in_cycle += 1;
-                                    }
-                                    Err(e) => {
-                                        warn!(
-                                            "Polling: Failed to process stable file {}: {}",
-                                            file_path.display(),
-                                            e
-                                        );
-                                    }
+                        info!(event = "discovered", path:% = file_path.display(); "Polling: Found file candidate: {}", file_path.display());
+
+                        match process_when_ready(file_path, options) {
+                            ReadyOutcome::Processed => files_processed_in_cycle += 1,
+                            ReadyOutcome::Failed => {
+                                files_failed_in_cycle += 1;
+                                if options.should_stop() {
+                                    warn!("Polling: Stopping after the first failure (--fail-fast)");
+                                    return Ok(());
                                 }
                             }
-                            Err(e) => {
-                                stability_checks_failed += 1;
-                                warn!(
-                                    "Polling: File {} stability check failed or file issue: {}. Skipping for this cycle.",
-                                    file_path.display(),
-                                    e
-                                );
-                            }
+                            ReadyOutcome::Skipped => files_skipped_in_cycle += 1,
                         }
                     }
                 }
                 Err(e) => {
-                    warn!("Polling: Error walking directory entry in {}: {}", source, e);
+                    options.record_walk_error(e);
+                    files_failed_in_cycle += 1;
+                    if options.should_stop() {
+                        warn!("Polling: Stopping after the first failure (--fail-fast)");
+                        return Ok(());
+                    }
                 }
             }
         }
 
         debug!(
-            "Polling cycle summary for {}: Found: {}, Stability Passed: {}, Stability Failed: {}, Processed OK: {}",
-            source, files_found_in_cycle, stability_checks_passed, stability_checks_failed, files_processed_successfully_in_cycle
+            "Polling cycle summary for {}: Found: {}, Processed OK: {}, Failed: {}, Skipped: {}",
+            source, files_found_in_cycle, files_processed_in_cycle, files_failed_in_cycle, files_skipped_in_cycle
         );
 
-        if let Err(e) = delete_empty_folders(source) {
+        if let Err(e) = delete_empty_folders(source, options) {
             warn!("Polling: Error during periodic deletion of empty folders in {}: {}", source, e);
         }
 
@@ -0,0 +1,59 @@
+use std::path::Path;
+
+/// Returns the filesystem type when `path` is on a network or FUSE mount, where
+/// file system events for changes made by other machines are never delivered.
+#[cfg(target_os = "linux")]
+pub fn network_filesystem(path: &Path) -> Option<&'static str> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    /// Magic numbers from `statfs(2)`.
+    const NETWORK_FILESYSTEMS: &[(u32, &str)] = &[
+        (0x6969, "nfs"),
+        (0x517B, "smb"),
+        (0xFF53_4D42, "cifs"),
+        (0xFE53_4D42, "smb2"),
+        (0x6573_5546, "fuse"),
+        (0x0102_1997, "9p"),
+        (0x00C3_6400, "ceph"),
+        (0x5346_414F, "afs"),
+    ];
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    // SAFETY: `statfs` is plain old data, so all zeroes is a valid value.
+    let mut stats: libc::statfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `c_path` is a valid NUL-terminated string and `stats` is a valid statfs buffer.
+    if unsafe { libc::statfs(c_path.as_ptr(), &mut stats) } != 0 {
+        return None;
+    }
+    // `f_type` is signed and 32 bits wide on some targets, so compare the low 32 bits
+    // rather than sign-extending magic numbers such as cifs' 0xFF534D42.
+    #[allow(clippy::unnecessary_cast)]
+    let magic = stats.f_type as u32;
+    NETWORK_FILESYSTEMS.iter().find(|(known, _)| *known == magic).map(|(_, name)| *name)
+}
+
+#[cfg(target_os = "macos")]
+pub fn network_filesystem(path: &Path) -> Option<&'static str> {
+    use std::ffi::{CStr, CString};
+    use std::os::unix::ffi::OsStrExt;
+
+    const NETWORK_FILESYSTEMS: &[&str] = &["nfs", "smbfs", "afpfs", "webdav", "macfuse", "osxfuse"];
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    // SAFETY: `statfs` is plain old data, so all zeroes is a valid value.
+    let mut stats: libc::statfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `c_path` is a valid NUL-terminated string and `stats` is a valid statfs buffer.
+    if unsafe { libc::statfs(c_path.as_ptr(), &mut stats) } != 0 {
+        return None;
+    }
+    // SAFETY: the kernel NUL-terminates `f_fstypename`.
+    let name = unsafe { CStr::from_ptr(stats.f_fstypename.as_ptr()) }.to_string_lossy();
+    NETWORK_FILESYSTEMS.iter().find(|known| **known == name).copied()
+}
+
+/// Other platforms are not checked; a failing watcher still triggers the fallback.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn network_filesystem(_path: &Path) -> Option<&'static str> {
+    None
+}
@@ -0,0 +1,143 @@
+use clap::ValueEnum;
+use indicatif::{HumanBytes, HumanDuration, ProgressBar, ProgressStyle};
+use log::info;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The bar currently on screen, so log lines can be printed around it.
+static ACTIVE_BAR: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+/// How progress through a directory scan is shown.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProgressMode {
+    /// A progress bar when stderr is a terminal, summary lines otherwise.
+    Auto,
+    /// Always draw a progress bar.
+    Bar,
+    /// Log a summary line every progress interval.
+    Log,
+    /// Show no progress.
+    Off,
+}
+
+/// Runs `f` with the progress bar, if any, hidden, so output does not tear it.
+pub fn suspend<R>(f: impl FnOnce() -> R) -> R {
+    let bar = ACTIVE_BAR.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+    match bar {
+        Some(bar) => bar.suspend(f),
+        None => f(),
+    }
+}
+
+/// Tracks files and bytes done while sorting a scanned directory.
+pub struct Progress {
+    bar: Option<ProgressBar>,
+    /// Interval between summary lines when there is no bar.
+    interval: Option<Duration>,
+    total_files: u64,
+    total_bytes: u64,
+    files_done: u64,
+    bytes_done: u64,
+    current_size: u64,
+    started: Instant,
+    last_summary: Instant,
+}
+
+impl Progress {
+    pub fn start(files: &[PathBuf], mode: ProgressMode, interval: Duration) -> Self {
+        let total_bytes = files.iter().filter_map(|path| fs::metadata(path).ok()).map(|metadata| metadata.len()).sum();
+        let use_bar = match mode {
+            ProgressMode::Auto => io::stderr().is_terminal(),
+            ProgressMode::Bar => true,
+            ProgressMode::Log | ProgressMode::Off => false,
+        };
+        let bar = use_bar.then(|| {
+            let bar = ProgressBar::new(total_bytes).with_style(
+                ProgressStyle::with_template(
+                    "[{elapsed_precise}] [{bar:30}] {prefix} files, {bytes}/{total_bytes} ({bytes_per_sec}, ETA {eta}) {wide_msg}",
+                )
+                .expect("progress template is valid")
+                .progress_chars("=> "),
+            );
+            bar.set_prefix(format!("0/{}", files.len()));
+            bar.enable_steady_tick(Duration::from_millis(200));
+            *ACTIVE_BAR.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(bar.clone());
+            bar
+        });
+        let now = Instant::now();
+        Progress {
+            bar,
+            interval: (!use_bar && mode != ProgressMode::Off).then_some(interval),
+            total_files: files.len() as u64,
+            total_bytes,
+            files_done: 0,
+            bytes_done: 0,
+            current_size: 0,
+            started: now,
+            last_summary: now,
+        }
+    }
+
+    /// Shows the file about to be sorted. Its size is read now, before it moves.
+    pub fn start_file(&mut self, path: &Path) {
+        self.current_size = fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0);
+        if let Some(bar) = &self.bar {
+            bar.set_message(path.display().to_string());
+        }
+    }
+
+    /// Counts the current file as done, whether or not it could be sorted.
+    pub fn finish_file(&mut self) {
+        self.files_done += 1;
+        self.bytes_done += self.current_size;
+        if let Some(bar) = &self.bar {
+            bar.set_prefix(format!("{}/{}", self.files_done, self.total_files));
+            bar.set_position(self.bytes_done);
+        }
+        if let Some(interval) = self.interval
+            && self.last_summary.elapsed() >= interval
+        {
+            self.last_summary = Instant::now();
+            self.log_summary();
+        }
+    }
+
+    /// Removes the bar and logs a final summary.
+    pub fn finish(self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+            *ACTIVE_BAR.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = None;
+        }
+        if self.bar.is_some() || self.interval.is_some() {
+            let elapsed = self.started.elapsed();
+            info!(
+                event = "progress_done", files_done = self.files_done, bytes_done = self.bytes_done;
+                "Processed {} files, {} in {} ({}/s)",
+                self.files_done, HumanBytes(self.bytes_done), HumanDuration(elapsed), HumanBytes(self.rate(elapsed) as u64)
+            );
+        }
+    }
+
+    /// Bytes per second so far.
+    fn rate(&self, elapsed: Duration) -> f64 {
+        self.bytes_done as f64 / elapsed.as_secs_f64().max(0.001)
+    }
+
+    fn log_summary(&self) {
+        let rate = self.rate(self.started.elapsed());
+        let eta = if self.bytes_done > 0 && rate > 0.0 {
+            HumanDuration(Duration::from_secs_f64(self.total_bytes.saturating_sub(self.bytes_done) as f64 / rate)).to_string()
+        } else {
+            "unknown".to_string()
+        };
+        info!(
+            event = "progress", files_done = self.files_done, total_files = self.total_files,
+            bytes_done = self.bytes_done, total_bytes = self.total_bytes;
+            "Progress: {}/{} files, {}/{} ({}/s, ETA {})",
+            self.files_done, self.total_files, HumanBytes(self.bytes_done), HumanBytes(self.total_bytes), HumanBytes(rate as u64), eta
+        );
+    }
+}
@@ -0,0 +1,268 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::video::find_box;
+
+/// Camera RAW extensions, several of which `mime_guess` does not classify as images.
+pub const RAW_EXTENSIONS: &[&str] = &[
+    "3fr", "arw", "cr2", "cr3", "dcr", "dng", "erf", "fff", "iiq", "kdc", "mos", "nef", "nrw",
+    "orf", "pef", "raf", "rw2", "rwl", "sr2", "srf", "srw",
+];
+
+/// UUID of the Canon box in a CR3 `moov` that holds the CMT1-CMT4 metadata boxes.
+const CR3_CANON_UUID: [u8; 16] = [
+    0x85, 0xc0, 0xb6, 0x87, 0x82, 0x0f, 0x11, 0xe0, 0x81, 0x11, 0xf4, 0xce, 0x46, 0x2b, 0x6a, 0x48,
+];
+
+const RAF_MAGIC: &[u8] = b"FUJIFILMCCD-RAW ";
+const TAG_EXIF_IFD_POINTER: u16 = 0x8769;
+const TAG_GPS_IFD_POINTER: u16 = 0x8825;
+/// Panasonic RW2 `JpgFromRaw`, an embedded JPEG preview with its own EXIF.
+const TAG_PANASONIC_JPG_FROM_RAW: u16 = 0x002e;
+
+pub fn is_raw_extension(ext: &str) -> bool {
+    RAW_EXTENSIONS.contains(&ext.to_lowercase().as_str())
+}
+
+/// Reads the EXIF blocks of a RAW file. Most formats have one block, but CR3 stores
+/// IFD0, the Exif IFD and the GPS IFD as separate TIFF structures.
+pub fn read_raw_exif(file_path: &Path) -> Result<Vec<exif::Exif>> {
+    let ext = file_path.extension().and_then(|ext| ext.to_str()).unwrap_or("").to_lowercase();
+    let reader = exif::Reader::new();
+    match ext.as_str() {
+        "cr3" => read_cr3_exif(&mut open(file_path)?),
+        "raf" => {
+            let jpeg = read_raf_jpeg(&mut open(file_path)?)?;
+            Ok(vec![reader.read_from_container(&mut Cursor::new(jpeg)).context("RAF: Failed to read embedded JPEG EXIF")?])
+        }
+        "orf" | "rw2" | "rwl" => {
+            // Olympus and Panasonic use TIFF with their own magic numbers.
+            let mut data = fs::read(file_path).with_context(|| format!("Failed to read {}", file_path.display()))?;
+            if data.len() < 8 {
+                anyhow::bail!("{}: File too short for a TIFF header", ext.to_uppercase());
+            }
+            match &data[0..2] {
+                b"II" => data[2..4].copy_from_slice(&[0x2a, 0x00]),
+                b"MM" => data[2..4].copy_from_slice(&[0x00, 0x2a]),
+                _ => anyhow::bail!("{}: Unknown byte order", ext.to_uppercase()),
+            }
+            let exif = reader.read_raw(data)?;
+            let mut blocks = Vec::new();
+            if let Some(field) = exif.get_field(exif::Tag(exif::Context::Tiff, TAG_PANASONIC_JPG_FROM_RAW), exif::In::PRIMARY)
+                && let exif::Value::Undefined(ref jpeg, _) = field.value
+                && let Ok(preview) = reader.read_from_container(&mut Cursor::new(jpeg))
+            {
+                blocks.push(preview);
+            }
+            blocks.insert(0, exif);
+            Ok(blocks)
+        }
+        _ => Ok(vec![reader.read_from_container(&mut open(file_path)?)?]),
+    }
+}
+
+fn open(file_path: &Path) -> Result<BufReader<File>> {
+    let file = File::open(file_path).with_context(|| format!("Failed to open {}", file_path.display()))?;
+    Ok(BufReader::new(file))
+}
+
+fn read_cr3_exif<R: Read + Seek>(reader: &mut R) -> Result<Vec<exif::Exif>> {
+    let moov_end = find_box(reader, b"moov", None).context("CR3: No moov box found")?;
+
+    let canon_end = loop {
+        let uuid_end = find_box(reader, b"uuid", Some(moov_end)).context("CR3: No Canon metadata box found")?;
+        let mut uuid = [0u8; 16];
+        reader.read_exact(&mut uuid)?;
+        if uuid == CR3_CANON_UUID {
+            break uuid_end;
+        }
+        reader.seek(SeekFrom::Start(uuid_end))?;
+    };
+
+    let exif_reader = exif::Reader::new();
+    let mut blocks = Vec::new();
+    let start = reader.stream_position()?;
+    for (name, pointer) in [(b"CMT1", None), (b"CMT2", Some(TAG_EXIF_IFD_POINTER)), (b"CMT4", Some(TAG_GPS_IFD_POINTER))] {
+        reader.seek(SeekFrom::Start(start))?;
+        let Ok(box_end) = find_box(reader, name, Some(canon_end)) else {
+            continue;
+        };
+        // find_box guarantees the box ends within the file.
+        let len = box_end.saturating_sub(reader.stream_position()?);
+        let mut tiff = Vec::new();
+        reader.take(len).read_to_end(&mut tiff)?;
+        if let Some(pointer) = pointer {
+            tiff = wrap_ifd(tiff, pointer)?;
+        }
+        blocks.push(exif_reader.read_raw(tiff).with_context(|| format!("CR3: Failed to read {}", String::from_utf8_lossy(name)))?);
+    }
+    if blocks.is_empty() {
+        anyhow::bail!("CR3: No CMT metadata boxes found");
+    }
+    Ok(blocks)
+}
+
+/// CMT2 and CMT4 hold the Exif and GPS IFDs as the first IFD of their own TIFF.
+/// A new IFD0 is appended that points at the original one, so the fields get the
+/// right context when parsed. The original offsets stay valid as nothing moves.
+fn wrap_ifd(mut tiff: Vec<u8>, pointer_tag: u16) -> Result<Vec<u8>> {
+    if tiff.len() < 8 {
+        anyhow::bail!("TIFF block too short");
+    }
+    let little_endian = match &tiff[0..2] {
+        b"II" => true,
+        b"MM" => false,
+        _ => anyhow::bail!("TIFF block has unknown byte order"),
+    };
+    let u16_bytes = |v: u16| if little_endian { v.to_le_bytes() } else { v.to_be_bytes() };
+    let u32_bytes = |v: u32| if little_endian { v.to_le_bytes() } else { v.to_be_bytes() };
+
+    let original_ifd = [tiff[4], tiff[5], tiff[6], tiff[7]];
+    if tiff.len() % 2 == 1 {
+        tiff.push(0);
+    }
+    let new_ifd = u32::try_from(tiff.len())?;
+    tiff.extend_from_slice(&u16_bytes(1));
+    tiff.extend_from_slice(&u16_bytes(pointer_tag));
+    tiff.extend_from_slice(&u16_bytes(4)); // LONG
+    tiff.extend_from_slice(&u32_bytes(1));
+    tiff.extend_from_slice(&original_ifd);
+    tiff.extend_from_slice(&u32_bytes(0));
+    tiff[4..8].copy_from_slice(&u32_bytes(new_ifd));
+    Ok(tiff)
+}
+
+/// RAF files start with a fixed header holding the offset and length of a JPEG
+/// preview, which carries the EXIF data.
+fn read_raf_jpeg<R: Read + Seek>(reader: &mut R) -> Result<Vec<u8>> {
+    let mut header = [0u8; 92];
+    reader.read_exact(&mut header).context("RAF: File too short for header")?;
+    if !header.starts_with(RAF_MAGIC) {
+        anyhow::bail!("RAF: Missing Fujifilm header");
+    }
+    let offset = u32::from_be_bytes([header[84], header[85], header[86], header[87]]) as u64;
+    let length = u32::from_be_bytes([header[88], header[89], header[90], header[91]]) as u64;
+    if offset + length > reader.seek(SeekFrom::End(0))? {
+        anyhow::bail!("RAF: JPEG preview extends past the end of the file");
+    }
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut jpeg = Vec::new();
+    reader.take(length).read_to_end(&mut jpeg)?;
+    Ok(jpeg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATE: &[u8] = b"2024:05:17 13:45:30\0";
+
+    /// A little-endian TIFF whose first IFD holds one ASCII field.
+    fn tiff_with_ascii(tag: u16, value: &[u8]) -> Vec<u8> {
+        let mut tiff = b"II\x2a\0".to_vec();
+        tiff.extend_from_slice(&8u32.to_le_bytes());
+        tiff.extend_from_slice(&1u16.to_le_bytes());
+        tiff.extend_from_slice(&tag.to_le_bytes());
+        tiff.extend_from_slice(&2u16.to_le_bytes());
+        tiff.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        tiff.extend_from_slice(&26u32.to_le_bytes());
+        tiff.extend_from_slice(&0u32.to_le_bytes());
+        tiff.extend_from_slice(value);
+        tiff
+    }
+
+    fn iso_box(name: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut data = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(name);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn cr3(cmt_boxes: &[u8]) -> Vec<u8> {
+        let mut canon = CR3_CANON_UUID.to_vec();
+        canon.extend_from_slice(cmt_boxes);
+        let mut other = [0u8; 16].to_vec();
+        other.extend_from_slice(&[0; 4]);
+        let mut moov = iso_box(b"uuid", &other);
+        moov.extend_from_slice(&iso_box(b"uuid", &canon));
+        let mut data = iso_box(b"ftyp", b"crx \0\0\0\x01");
+        data.extend_from_slice(&iso_box(b"moov", &moov));
+        data
+    }
+
+    fn raf(offset: u32, length: u32, body: &[u8]) -> Vec<u8> {
+        let mut data = RAF_MAGIC.to_vec();
+        data.resize(84, 0);
+        data.extend_from_slice(&offset.to_be_bytes());
+        data.extend_from_slice(&length.to_be_bytes());
+        data.extend_from_slice(body);
+        data
+    }
+
+    fn field_text(exif: &exif::Exif, tag: exif::Tag) -> Option<String> {
+        exif.get_field(tag, exif::In::PRIMARY).map(|field| field.display_value().to_string())
+    }
+
+    #[test]
+    fn wrap_ifd_gives_fields_their_context() {
+        let tiff = wrap_ifd(tiff_with_ascii(0x9003, DATE), TAG_EXIF_IFD_POINTER).unwrap();
+        let exif = exif::Reader::new().read_raw(tiff).unwrap();
+        assert_eq!(field_text(&exif, exif::Tag::DateTimeOriginal).as_deref(), Some("2024-05-17 13:45:30"));
+    }
+
+    #[test]
+    fn wrap_ifd_keeps_big_endian_and_pads_odd_lengths() {
+        let mut tiff = b"MM\0\x2a\0\0\0\x08\0\0\0\0\0\0\0".to_vec();
+        let original_len = tiff.len();
+        tiff = wrap_ifd(tiff, TAG_GPS_IFD_POINTER).unwrap();
+        assert_eq!(&tiff[4..8], &(original_len as u32 + 1).to_be_bytes());
+        assert_eq!(&tiff[original_len + 3..original_len + 5], &TAG_GPS_IFD_POINTER.to_be_bytes());
+        assert_eq!(&tiff[original_len + 11..original_len + 15], &8u32.to_be_bytes());
+    }
+
+    #[test]
+    fn wrap_ifd_rejects_short_or_unknown_blocks() {
+        assert!(wrap_ifd(b"II\x2a\0".to_vec(), TAG_EXIF_IFD_POINTER).is_err());
+        assert!(wrap_ifd(b"XX\x2a\0\x08\0\0\0".to_vec(), TAG_EXIF_IFD_POINTER).is_err());
+    }
+
+    #[test]
+    fn read_cr3_exif_reads_cmt_boxes() {
+        let mut cmt = iso_box(b"CMT1", &tiff_with_ascii(0x0110, b"Canon EOS R6\0"));
+        cmt.extend_from_slice(&iso_box(b"CMT2", &tiff_with_ascii(0x9003, DATE)));
+        let blocks = read_cr3_exif(&mut Cursor::new(cr3(&cmt))).unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(field_text(&blocks[0], exif::Tag::Model).as_deref(), Some("\"Canon EOS R6\""));
+        assert_eq!(field_text(&blocks[1], exif::Tag::DateTimeOriginal).as_deref(), Some("2024-05-17 13:45:30"));
+    }
+
+    #[test]
+    fn read_cr3_exif_rejects_missing_and_oversized_boxes() {
+        assert!(read_cr3_exif(&mut Cursor::new(cr3(&[]))).is_err());
+
+        let mut oversized = u32::MAX.to_be_bytes().to_vec();
+        oversized.extend_from_slice(b"CMT1");
+        oversized.extend_from_slice(&tiff_with_ascii(0x0110, b"Canon EOS R6\0"));
+        assert!(read_cr3_exif(&mut Cursor::new(cr3(&oversized))).is_err());
+
+        let mut truncated = cr3(&iso_box(b"CMT1", &tiff_with_ascii(0x0110, b"Canon EOS R6\0")));
+        truncated.truncate(truncated.len() - 4);
+        assert!(read_cr3_exif(&mut Cursor::new(truncated)).is_err());
+    }
+
+    #[test]
+    fn read_raf_jpeg_reads_preview() {
+        let jpeg = read_raf_jpeg(&mut Cursor::new(raf(96, 4, &[0, 0, 0, 0, 0xFF, 0xD8, 0xFF, 0xD9]))).unwrap();
+        assert_eq!(jpeg, [0xFF, 0xD8, 0xFF, 0xD9]);
+    }
+
+    #[test]
+    fn read_raf_jpeg_rejects_bad_headers_and_lengths() {
+        assert!(read_raf_jpeg(&mut Cursor::new(raf(96, 4, &[])[..60].to_vec())).is_err());
+        assert!(read_raf_jpeg(&mut Cursor::new(vec![0u8; 100])).is_err());
+        assert!(read_raf_jpeg(&mut Cursor::new(raf(96, u32::MAX, &[0; 8]))).is_err());
+        assert!(read_raf_jpeg(&mut Cursor::new(raf(u32::MAX, u32::MAX, &[0; 8]))).is_err());
+    }
+}
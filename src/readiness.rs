@@ -0,0 +1,182 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use log::{debug, info, warn};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often a file is looked at while waiting for it to become ready.
+const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Ways of telling that a file has been completely written.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReadinessCheck {
+    /// Size and modification time unchanged for the quiet period.
+    Quiet,
+    /// No other process holds a lock on the file (or, on Windows, has it open).
+    ExclusiveOpen,
+    /// A sentinel file such as `photo.jpg.done` exists next to the file.
+    Sentinel,
+    /// The name does not end in a temporary suffix such as `.part`.
+    TempSuffix,
+}
+
+/// Whether a file can be sorted now.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Ready {
+    Yes,
+    /// Not yet, and waiting will not help. An event or rescan will bring the file
+    /// back once it is renamed or its sentinel appears.
+    NotYet(&'static str),
+}
+
+/// Decides when a file that appeared in the source is ready to be sorted.
+#[derive(Debug, Clone)]
+pub struct Readiness {
+    pub checks: Vec<ReadinessCheck>,
+    pub quiet_period: Duration,
+    /// How long to wait for the quiet and exclusive-open checks before giving up.
+    pub timeout: Duration,
+    /// Files last modified longer ago than this skip the waiting checks.
+    pub max_age: Option<Duration>,
+    pub temp_suffixes: Vec<String>,
+    pub sentinel_suffix: String,
+}
+
+impl Default for Readiness {
+    fn default() -> Self {
+        Readiness {
+            checks: vec![ReadinessCheck::Quiet],
+            quiet_period: Duration::from_millis(1500),
+            timeout: Duration::from_secs(30 * 60),
+            max_age: None,
+            temp_suffixes: Vec::new(),
+            sentinel_suffix: ".done".to_string(),
+        }
+    }
+}
+
+impl Readiness {
+    fn uses(&self, check: ReadinessCheck) -> bool {
+        self.checks.contains(&check)
+    }
+
+    /// Whether the path is a sentinel file rather than media to sort.
+    pub fn is_sentinel(&self, path: &Path) -> bool {
+        self.uses(ReadinessCheck::Sentinel) && self.sentinel_target(path).is_some()
+    }
+
+    /// The file a sentinel refers to: `photo.jpg.done` gives `photo.jpg`.
+    fn sentinel_target(&self, path: &Path) -> Option<PathBuf> {
+        let name = path.file_name()?.to_str()?;
+        let target = name.strip_suffix(&self.sentinel_suffix).filter(|target| !target.is_empty())?;
+        Some(path.with_file_name(target))
+    }
+
+    fn sentinel_path(&self, path: &Path) -> PathBuf {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(&self.sentinel_suffix);
+        path.with_file_name(name)
+    }
+
+    /// The file to check when `path` changed. For a sentinel file that is the file
+    /// it marks as complete, so the sentinel appearing triggers sorting.
+    pub fn file_to_process(&self, path: &Path) -> PathBuf {
+        if self.uses(ReadinessCheck::Sentinel)
+            && let Some(target) = self.sentinel_target(path)
+        {
+            return target;
+        }
+        path.to_path_buf()
+    }
+
+    /// Runs the checks that do not involve waiting: temporary names and sentinels.
+    pub fn quick_check(&self, path: &Path) -> Ready {
+        let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+        if self.uses(ReadinessCheck::TempSuffix) && self.temp_suffixes.iter().any(|suffix| name.ends_with(suffix.as_str())) {
+            return Ready::NotYet("temporary file name");
+        }
+        if self.uses(ReadinessCheck::Sentinel) && !self.sentinel_path(path).exists() {
+            return Ready::NotYet("no sentinel file yet");
+        }
+        Ready::Yes
+    }
+
+    /// Runs all configured checks, waiting where that can help. Returns an error
+    /// when the file disappears or does not become ready before the timeout.
+    pub fn wait(&self, path: &Path) -> Result<Ready> {
+        if let Ready::NotYet(reason) = self.quick_check(path) {
+            return Ok(Ready::NotYet(reason));
+        }
+
+        let modified = fs::metadata(path)?.modified().ok();
+        if let (Some(max_age), Some(modified)) = (self.max_age, modified)
+            && SystemTime::now().duration_since(modified).is_ok_and(|age| age > max_age)
+        {
+            debug!("File {} is older than the maximum age. Treating it as ready.", path.display());
+            return Ok(Ready::Yes);
+        }
+
+        let deadline = Instant::now() + self.timeout;
+        if self.uses(ReadinessCheck::Quiet) {
+            self.wait_for_quiet(path, deadline)?;
+        }
+        if self.uses(ReadinessCheck::ExclusiveOpen) {
+            self.wait_for_exclusive_open(path, deadline)?;
+        }
+        Ok(Ready::Yes)
+    }
+
+    /// Removes the sentinel once its file has been moved out of the source. In
+    /// copy mode both are left alone.
+    pub fn finish(&self, path: &Path, moved: bool) {
+        if !moved || !self.uses(ReadinessCheck::Sentinel) {
+            return;
+        }
+        let sentinel = self.sentinel_path(path);
+        if let Err(e) = fs::remove_file(&sentinel) {
+            warn!("Failed to remove sentinel file {}: {}", sentinel.display(), e);
+        }
+    }
+
+    /// Waits until the size and modification time stay the same for the quiet period.
+    fn wait_for_quiet(&self, path: &Path, deadline: Instant) -> Result<()> {
+        let signature = |path: &Path| fs::metadata(path).map(|metadata| (metadata.len(), metadata.modified().ok()));
+        let mut previous = signature(path)?;
+        let mut quiet_since = Instant::now();
+        debug!("Waiting for file {} to be unchanged for {:?}", path.display(), self.quiet_period);
+        loop {
+            thread::sleep(CHECK_INTERVAL);
+            let current = signature(path)?;
+            if current != previous {
+                debug!("File {} changed ({:?} -> {:?}). Restarting the quiet period.", path.display(), previous, current);
+                previous = current;
+                quiet_since = Instant::now();
+            } else if quiet_since.elapsed() >= self.quiet_period {
+                info!(event = "stable", path:% = path.display(), size = current.0; "File {} stabilised with size {}.", path.display(), current.0);
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                anyhow::bail!("File {} was still changing after {:?}", path.display(), self.timeout);
+            }
+        }
+    }
+
+    /// Waits until the file can be locked exclusively, meaning no writer holds it.
+    fn wait_for_exclusive_open(&self, path: &Path, deadline: Instant) -> Result<()> {
+        loop {
+            let locked = File::open(path).map(|file| file.try_lock().is_ok());
+            match locked {
+                Ok(true) => return Ok(()),
+                Ok(false) => debug!("File {} is locked by another process", path.display()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(e.into()),
+                Err(e) => debug!("File {} cannot be opened yet: {}", path.display(), e),
+            }
+            if Instant::now() >= deadline {
+                anyhow::bail!("File {} was still in use after {:?}", path.display(), self.timeout);
+            }
+            thread::sleep(CHECK_INTERVAL);
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use anyhow::{Context, Result};
+use log::warn;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Appends one JSON line per processed file, so runs can be audited afterwards.
+#[derive(Debug)]
+pub struct Report {
+    file: Mutex<File>,
+}
+
+impl Report {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open report file {}", path.display()))?;
+        Ok(Report { file: Mutex::new(file) })
+    }
+
+    /// Writes a record. Failures are logged rather than returned so that a full
+    /// disk or similar does not stop files being sorted.
+    pub fn record<T: Serialize>(&self, record: &T) {
+        let line = match serde_json::to_string(record) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("Failed to serialise report record: {}", e);
+                return;
+            }
+        };
+        let mut file = self.file.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Err(e) = writeln!(file, "{}", line) {
+            warn!("Failed to write report record: {}", e);
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use crate::error::SortError;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Name of the queue file kept in the destination.
+pub const RETRY_FILE_NAME: &str = ".shuttersort-retry.json";
+
+/// Longest wait between two attempts, however many times a file has failed.
+const MAX_RETRY_DELAY: Duration = Duration::hours(1);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RetryEntry {
+    path: PathBuf,
+    attempts: u32,
+    next_attempt: DateTime<Utc>,
+    error: String,
+    /// Set once the file has used up its attempts. The entry is kept as a record
+    /// of the failure and the file is not tried again until it changes.
+    gave_up: bool,
+    /// Size and modification time of the file when it last failed. A file that
+    /// changes since, for example because it was fixed or replaced, starts afresh.
+    #[serde(default)]
+    size: Option<u64>,
+    #[serde(default)]
+    modified: Option<DateTime<Utc>>,
+}
+
+impl RetryEntry {
+    fn file_changed(&self) -> bool {
+        (self.size, self.modified) != signature(&self.path)
+    }
+}
+
+/// What happens to a file after a failed attempt.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Retry {
+    At(DateTime<Utc>),
+    GiveUp,
+}
+
+/// Files that failed to sort, retried with exponential backoff.
+///
+/// The queue is saved as JSON in the destination after every change, so pending
+/// retries and the list of files given up on survive restarts.
+#[derive(Debug)]
+pub struct RetryQueue {
+    file: PathBuf,
+    entries: Mutex<Vec<RetryEntry>>,
+    max_attempts: u32,
+    base_delay: Duration,
+}
+
+impl RetryQueue {
+    pub fn load(destination: &Path, max_attempts: u32, base_delay: Duration) -> Result<Self> {
+        let file = destination.join(RETRY_FILE_NAME);
+        let mut entries: Vec<RetryEntry> = if file.exists() {
+            let content = fs::read_to_string(&file).with_context(|| format!("Failed to read retry queue {}", file.display()))?;
+            serde_json::from_str(&content).with_context(|| format!("Invalid retry queue {}", file.display()))?
+        } else {
+            Vec::new()
+        };
+        // Files that are gone, such as quarantined ones, or that changed since they
+        // failed no longer need an entry.
+        entries.retain(|entry| entry.path.exists() && !entry.file_changed());
+        let pending = entries.iter().filter(|entry| !entry.gave_up).count();
+        if pending > 0 {
+            info!("Loaded {} pending retries from {}", pending, file.display());
+        }
+        Ok(RetryQueue { file, entries: Mutex::new(entries), max_attempts, base_delay })
+    }
+
+    /// Records a failed attempt and decides when to try again. Metadata errors
+    /// will not go away by themselves, so those files are given up on at once.
+    pub fn schedule(&self, path: &Path, error: &SortError) -> Retry {
+        let path = absolute(path);
+        let mut entries = self.lock();
+        let index = match entries.iter().position(|entry| entry.path == path) {
+            Some(index) => index,
+            None => {
+                entries.push(RetryEntry {
+                    path,
+                    attempts: 0,
+                    next_attempt: Utc::now(),
+                    error: String::new(),
+                    gave_up: false,
+                    size: None,
+                    modified: None,
+                });
+                entries.len() - 1
+            }
+        };
+        let entry = &mut entries[index];
+        if entry.gave_up && entry.file_changed() {
+            entry.attempts = 0;
+            entry.gave_up = false;
+        }
+        (entry.size, entry.modified) = signature(&entry.path);
+        entry.attempts += 1;
+        entry.error = format!("{} error: {}", error.category(), error);
+        let retry = if entry.attempts >= self.max_attempts || matches!(error, SortError::Metadata { .. }) {
+            entry.gave_up = true;
+            Retry::GiveUp
+        } else {
+            let delay = self.base_delay * 2i32.saturating_pow(entry.attempts - 1).min(1 << 16);
+            entry.next_attempt = Utc::now() + delay.min(MAX_RETRY_DELAY);
+            Retry::At(entry.next_attempt)
+        };
+        debug!("Retry entry for {}: {:?}", entry.path.display(), entry);
+        self.save(&entries);
+        retry
+    }
+
+    /// Files whose next attempt is due.
+    pub fn due(&self, now: DateTime<Utc>) -> Vec<PathBuf> {
+        self.lock().iter().filter(|entry| !entry.gave_up && entry.next_attempt <= now).map(|entry| entry.path.clone()).collect()
+    }
+
+    /// Whether a file is waiting for its next attempt or was given up on, so it
+    /// should not be picked up by a rescan yet. A file that changed since it failed
+    /// is forgotten and may be tried at once.
+    pub fn is_waiting(&self, path: &Path) -> bool {
+        let path = absolute(path);
+        let now = Utc::now();
+        let mut entries = self.lock();
+        let Some(index) = entries.iter().position(|entry| entry.path == path) else {
+            return false;
+        };
+        if entries[index].file_changed() {
+            info!("{} changed since it failed. Trying it again.", path.display());
+            entries.remove(index);
+            self.save(&entries);
+            return false;
+        }
+        entries[index].gave_up || entries[index].next_attempt > now
+    }
+
+    /// Forgets a pending retry, once the file was sorted or has disappeared.
+    pub fn resolve(&self, path: &Path) {
+        let path = absolute(path);
+        let mut entries = self.lock();
+        let before = entries.len();
+        entries.retain(|entry| entry.gave_up || entry.path != path);
+        if entries.len() != before {
+            self.save(&entries);
+        }
+    }
+
+    /// Drops a file's entry even if it was given up on, once the file has been
+    /// moved to the failed folder.
+    pub fn forget(&self, path: &Path) {
+        let path = absolute(path);
+        let mut entries = self.lock();
+        let before = entries.len();
+        entries.retain(|entry| entry.path != path);
+        if entries.len() != before {
+            self.save(&entries);
+        }
+    }
+
+    pub fn file(&self) -> &Path {
+        &self.file
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<RetryEntry>> {
+        self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Writes the queue via a temporary file, so a crash never leaves it half written.
+    /// Failures are logged, like report writes.
+    fn save(&self, entries: &[RetryEntry]) {
+        let temp = self.file.with_extension("json.tmp");
+        let result = serde_json::to_string_pretty(entries)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| fs::write(&temp, json).map_err(anyhow::Error::from))
+            .and_then(|_| fs::rename(&temp, &self.file).map_err(anyhow::Error::from));
+        if let Err(e) = result {
+            warn!("Failed to save retry queue {}: {}", self.file.display(), e);
+        }
+    }
+}
+
+fn signature(path: &Path) -> (Option<u64>, Option<DateTime<Utc>>) {
+    match fs::metadata(path) {
+        Ok(metadata) => (Some(metadata.len()), metadata.modified().ok().map(DateTime::<Utc>::from)),
+        Err(_) => (None, None),
+    }
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
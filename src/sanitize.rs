@@ -0,0 +1,241 @@
+use clap::ValueEnum;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::Path;
+
+/// Names Windows reserves for devices, with or without an extension.
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Characters FAT, exFAT and SMB shares do not allow in names, besides control characters.
+const WINDOWS_RESERVED_CHARS: &[char] = &['<', '>', ':', '"', '|', '?', '*'];
+
+/// The kind of filesystem the destination lives on, which decides what a valid name is.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PathProfile {
+    /// Linux and macOS filesystems: only `/` and NUL are invalid.
+    Posix,
+    /// FAT32 and exFAT, e.g. SD cards and USB drives. Lengths count UTF-16 units.
+    Fat,
+    /// Windows and Samba shares. Lengths count bytes.
+    Smb,
+}
+
+/// Turns metadata values and file names into safe single path components.
+#[derive(Debug, Clone)]
+pub struct Sanitizer {
+    profile: PathProfile,
+    max_length: usize,
+}
+
+impl Sanitizer {
+    pub fn new(profile: PathProfile, max_length: usize) -> Self {
+        Sanitizer { profile, max_length: max_length.max(1) }
+    }
+
+    /// Sanitises a folder name.
+    pub fn folder(&self, value: &str) -> String {
+        let cleaned = self.clean(value);
+        self.finish(truncate(&cleaned, self.max_length, self.profile))
+    }
+
+    /// Sanitises a file name, keeping its extension when the name has to be shortened.
+    pub fn file_name(&self, value: &str) -> String {
+        let cleaned = self.clean(value);
+        let (stem, ext) = match cleaned.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() && !ext.is_empty() => (stem, Some(ext)),
+            _ => (cleaned.as_str(), None),
+        };
+        let name = match ext {
+            Some(ext) => {
+                let ext_len = measure(ext, self.profile) + 1;
+                let stem_budget = self.max_length.saturating_sub(ext_len).max(1);
+                format!("{}.{}", truncate(stem, stem_budget, self.profile), ext)
+            }
+            None => truncate(stem, self.max_length, self.profile),
+        };
+        self.finish(name)
+    }
+
+    /// Appends `suffix` to the stem of a sanitised file name, such as `_2` for a name
+    /// already taken. The stem is shortened instead of the suffix or extension, so the
+    /// name stays within the length limit.
+    pub fn suffixed_file_name(&self, name: &str, suffix: &str) -> String {
+        let (stem, ext) = match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() && !ext.is_empty() => (stem, Some(ext)),
+            _ => (name, None),
+        };
+        let reserved = measure(suffix, self.profile) + ext.map_or(0, |ext| measure(ext, self.profile) + 1);
+        let stem = truncate(stem, self.max_length.saturating_sub(reserved).max(1), self.profile);
+        match ext {
+            Some(ext) => format!("{}{}.{}", stem, suffix, ext),
+            None => format!("{}{}", stem, suffix),
+        }
+    }
+
+    fn clean(&self, value: &str) -> String {
+        let windows = self.profile != PathProfile::Posix;
+        value
+            .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+            .chars()
+            .filter(|c| !c.is_control())
+            .map(|c| match c {
+                '/' | '\\' => '-',
+                c if windows && WINDOWS_RESERVED_CHARS.contains(&c) => '_',
+                c => c,
+            })
+            .collect()
+    }
+
+    fn finish(&self, mut name: String) -> String {
+        if self.profile != PathProfile::Posix {
+            // Windows silently drops trailing dots and spaces, so `Inc.` and `Inc` would clash.
+            let trimmed_len = name.trim_end_matches(['.', ' ']).len();
+            name.truncate(trimmed_len);
+            let base = name.split('.').next().unwrap_or("");
+            if WINDOWS_RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(base)) {
+                name.insert(0, '_');
+            }
+        }
+        if name.is_empty() || name == "." || name == ".." {
+            name = "_".to_string();
+        }
+        name
+    }
+}
+
+fn measure(value: &str, profile: PathProfile) -> usize {
+    match profile {
+        PathProfile::Fat => value.encode_utf16().count(),
+        PathProfile::Posix | PathProfile::Smb => value.len(),
+    }
+}
+
+/// Shortens a value to the length limit without splitting a character.
+fn truncate(value: &str, max_length: usize, profile: PathProfile) -> String {
+    let mut truncated = String::new();
+    let mut length = 0;
+    for c in value.chars() {
+        let mut buf = [0u8; 4];
+        length += measure(c.encode_utf8(&mut buf), profile);
+        if length > max_length {
+            break;
+        }
+        truncated.push(c);
+    }
+    truncated
+}
+
+/// Checks whether names in `dir` are matched case-insensitively by creating a
+/// lowercase probe file and looking it up in upper case. Linux itself is case
+/// sensitive, but SMB shares and exFAT or FAT mounts are not.
+pub fn probe_case_insensitive(dir: &Path) -> io::Result<bool> {
+    fs::create_dir_all(dir)?;
+    let name = format!(".shuttersort-case-probe-{}", std::process::id());
+    let probe = dir.join(&name);
+    OpenOptions::new().write(true).create_new(true).open(&probe)?;
+    let result = fs::symlink_metadata(dir.join(name.to_uppercase()));
+    fs::remove_file(&probe)?;
+    match result {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn posix() -> Sanitizer {
+        Sanitizer::new(PathProfile::Posix, 16)
+    }
+
+    fn fat() -> Sanitizer {
+        Sanitizer::new(PathProfile::Fat, 16)
+    }
+
+    fn smb() -> Sanitizer {
+        Sanitizer::new(PathProfile::Smb, 16)
+    }
+
+    #[test]
+    fn posix_only_replaces_separators_and_control_characters() {
+        assert_eq!(posix().folder("  EOS R6/Mark\\II\t"), "EOS R6-Mark-II");
+        assert_eq!(posix().folder("a\u{7}b<c>:d?"), "ab<c>:d?");
+        assert_eq!(posix().folder("Inc. "), "Inc.");
+        assert_eq!(posix().folder("CON"), "CON");
+    }
+
+    #[test]
+    fn windows_profiles_replace_reserved_characters_and_names() {
+        for sanitizer in [fat(), smb()] {
+            assert_eq!(sanitizer.folder("a<b>:c\"d|e?f*"), "a_b__c_d_e_f_");
+            assert_eq!(sanitizer.folder("Canon Inc. ."), "Canon Inc");
+            assert_eq!(sanitizer.folder("con"), "_con");
+            assert_eq!(sanitizer.file_name("NUL.txt"), "_NUL.txt");
+            assert_eq!(sanitizer.folder("CONSOLE"), "CONSOLE");
+        }
+    }
+
+    #[test]
+    fn empty_and_dot_names_become_placeholders() {
+        for sanitizer in [posix(), fat(), smb()] {
+            assert_eq!(sanitizer.folder(""), "_");
+            assert_eq!(sanitizer.folder("\u{1}\u{2}"), "_");
+            assert_eq!(sanitizer.folder(".."), "_");
+        }
+        assert_eq!(posix().folder("."), "_");
+        assert_eq!(fat().folder("..."), "_");
+    }
+
+    #[test]
+    fn lengths_are_measured_per_profile() {
+        // 'é' is two bytes but one UTF-16 unit; '😀' is four bytes and two UTF-16 units.
+        assert_eq!(posix().folder(&"é".repeat(10)), "é".repeat(8));
+        assert_eq!(smb().folder(&"é".repeat(10)), "é".repeat(8));
+        assert_eq!(fat().folder(&"é".repeat(20)), "é".repeat(16));
+        assert_eq!(fat().folder(&"😀".repeat(10)), "😀".repeat(8));
+        assert_eq!(posix().folder(&"😀".repeat(10)), "😀".repeat(4));
+    }
+
+    #[test]
+    fn truncation_can_expose_trailing_spaces_on_windows() {
+        assert_eq!(fat().folder("abcdefghijklmno pq"), "abcdefghijklmno");
+        assert_eq!(posix().folder("abcdefghijklmno pq"), "abcdefghijklmno ");
+    }
+
+    #[test]
+    fn file_names_keep_their_extension() {
+        assert_eq!(posix().file_name("abcdefghijklmnopqrstuvwxyz.jpeg"), "abcdefghijk.jpeg");
+        assert_eq!(fat().file_name("éééééééééééééééééééé.jpg"), "éééééééééééé.jpg");
+        assert_eq!(smb().file_name("éééééééééééééééééééé.jpg"), "éééééé.jpg");
+        assert_eq!(posix().file_name("a/b.jpg"), "a-b.jpg");
+        assert_eq!(posix().file_name(".hidden"), ".hidden");
+        assert_eq!(posix().file_name("abcdefghijklmnopqrstuvwxyz"), "abcdefghijklmnop");
+    }
+
+    #[test]
+    fn suffixed_file_names_stay_within_the_limit() {
+        assert_eq!(posix().suffixed_file_name("a.jpg", "_2"), "a_2.jpg");
+        assert_eq!(posix().suffixed_file_name("abcdefghijkl.JPG", "_1"), "abcdefghij_1.JPG");
+        assert_eq!(posix().suffixed_file_name("abcdefghijkl.JPG", "_10"), "abcdefghi_10.JPG");
+        assert_eq!(posix().suffixed_file_name("abcdefghijklmnop", "_1"), "abcdefghijklmn_1");
+        assert_eq!(fat().suffixed_file_name("éééééééééééé.jpg", "_1"), "éééééééééé_1.jpg");
+        assert_eq!(smb().suffixed_file_name("éééééé.jpg", "_1"), "ééééé_1.jpg");
+    }
+
+    #[test]
+    fn probe_finds_case_sensitive_directory() {
+        let dir = std::env::temp_dir().join(format!("shuttersort-probe-{}", std::process::id()));
+        let result = probe_case_insensitive(&dir);
+        let leftovers = fs::read_dir(&dir).map(|entries| entries.count()).unwrap_or(0);
+        fs::remove_dir_all(&dir).unwrap();
+        if cfg!(target_os = "linux") {
+            assert!(!result.unwrap());
+        }
+        assert_eq!(leftovers, 0);
+    }
+}
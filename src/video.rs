@@ -0,0 +1,461 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use log::debug;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// How much of an AVCHD stream is scanned for the MDPM block.
+const AVCHD_SCAN_LIMIT: u64 = 4 * 1024 * 1024;
+
+const MATROSKA_EBML: u32 = 0x1A45_DFA3;
+const MATROSKA_SEGMENT: u32 = 0x1853_8067;
+const MATROSKA_INFO: u32 = 0x1549_A966;
+const MATROSKA_CLUSTER: u32 = 0x1F43_B675;
+const MATROSKA_DATE_UTC: u32 = 0x4461;
+
+/// Largest IDIT chunk accepted. The date string is a few dozen bytes at most.
+const AVI_IDIT_MAX_SIZE: i64 = 64;
+
+/// Marks the camera metadata block in the H.264 SEI of AVCHD streams.
+const MDPM_MARKER: &[u8] = b"MDPM";
+
+/// Extracts the recording date from containers that mediameta does not handle.
+pub fn extract_container_date(file_path: &Path, ext: &str) -> Result<DateTime<Utc>> {
+    let file = File::open(file_path).with_context(|| format!("Failed to open video file {}", file_path.display()))?;
+    let mut reader = BufReader::new(file);
+    match ext {
+        "mkv" | "webm" => matroska_date(&mut reader),
+        "avi" => avi_date(&mut reader),
+        "mts" | "m2ts" => avchd_date(&mut reader),
+        "3gp" | "3g2" => mvhd_date(&mut reader),
+        _ => anyhow::bail!("Not a supported video container: {}", ext),
+    }
+}
+
+/// Reads Segment > Info > DateUTC, nanoseconds since 2001-01-01T00:00:00 UTC.
+fn matroska_date<R: Read + Seek>(reader: &mut R) -> Result<DateTime<Utc>> {
+    let (id, size) = read_ebml_element(reader)?;
+    if id != MATROSKA_EBML {
+        anyhow::bail!("Matroska: Missing EBML header");
+    }
+    skip_ebml(reader, size)?;
+
+    loop {
+        let (id, size) = read_ebml_element(reader).context("Matroska: No Segment found")?;
+        if id == MATROSKA_SEGMENT {
+            break;
+        }
+        skip_ebml(reader, size)?;
+    }
+
+    loop {
+        let (id, size) = read_ebml_element(reader).context("Matroska: No Info element found")?;
+        match id {
+            MATROSKA_INFO => {
+                let end = size.map(|size| reader.stream_position().map(|pos| pos.saturating_add(size))).transpose()?;
+                while end.is_none_or(|end| reader.stream_position().is_ok_and(|pos| pos < end)) {
+                    let (child_id, child_size) = read_ebml_element(reader)?;
+                    if child_id == MATROSKA_DATE_UTC {
+                        let mut buf = [0u8; 8];
+                        if child_size != Some(8) {
+                            anyhow::bail!("Matroska: DateUTC has unexpected size {:?}", child_size);
+                        }
+                        reader.read_exact(&mut buf)?;
+                        let nanos = i64::from_be_bytes(buf);
+                        let epoch = Utc.with_ymd_and_hms(2001, 1, 1, 0, 0, 0).unwrap();
+                        return Ok(epoch + chrono::Duration::nanoseconds(nanos));
+                    }
+                    skip_ebml(reader, child_size)?;
+                }
+                anyhow::bail!("Matroska: No DateUTC in Info element");
+            }
+            MATROSKA_CLUSTER => anyhow::bail!("Matroska: Reached media clusters without finding Info"),
+            _ => skip_ebml(reader, size)?,
+        }
+    }
+}
+
+/// Reads an EBML element header. A size of `None` means the size is unknown.
+fn read_ebml_element<R: Read>(reader: &mut R) -> Result<(u32, Option<u64>)> {
+    let (id, _) = read_vint(reader, true)?;
+    let (size, all_ones) = read_vint(reader, false)?;
+    Ok((id as u32, if all_ones { None } else { Some(size) }))
+}
+
+/// Reads an EBML variable-length integer, keeping the length marker for element IDs.
+fn read_vint<R: Read>(reader: &mut R, keep_marker: bool) -> Result<(u64, bool)> {
+    let mut first = [0u8; 1];
+    reader.read_exact(&mut first)?;
+    let length = first[0].leading_zeros() as usize + 1;
+    if length > 8 {
+        anyhow::bail!("Matroska: Invalid variable-length integer");
+    }
+    let marker_mask = 0xFFu64 >> length;
+    let mut value = if keep_marker { first[0] as u64 } else { first[0] as u64 & marker_mask };
+    let mut all_ones = value == marker_mask;
+    for _ in 1..length {
+        let mut byte = [0u8; 1];
+        reader.read_exact(&mut byte)?;
+        value = (value << 8) | byte[0] as u64;
+        all_ones &= byte[0] == 0xFF;
+    }
+    Ok((value, all_ones))
+}
+
+fn skip_ebml<R: Seek>(reader: &mut R, size: Option<u64>) -> Result<()> {
+    match size {
+        Some(size) => {
+            reader.seek(SeekFrom::Current(i64::try_from(size)?))?;
+            Ok(())
+        }
+        None => anyhow::bail!("Matroska: Cannot skip element of unknown size"),
+    }
+}
+
+/// Reads the IDIT chunk from the AVI header list.
+fn avi_date<R: Read + Seek>(reader: &mut R) -> Result<DateTime<Utc>> {
+    let mut header = [0u8; 12];
+    reader.read_exact(&mut header)?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"AVI " {
+        anyhow::bail!("AVI: Missing RIFF AVI header");
+    }
+
+    loop {
+        let mut chunk = [0u8; 8];
+        reader.read_exact(&mut chunk).context("AVI: No IDIT chunk found")?;
+        let size = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as i64;
+        match &chunk[0..4] {
+            b"LIST" => {
+                if size < 4 {
+                    anyhow::bail!("AVI: LIST chunk too short");
+                }
+                let mut list_type = [0u8; 4];
+                reader.read_exact(&mut list_type)?;
+                if &list_type != b"hdrl" {
+                    if &list_type == b"movi" {
+                        anyhow::bail!("AVI: Reached movie data without finding IDIT");
+                    }
+                    reader.seek(SeekFrom::Current(size - 4 + (size & 1)))?;
+                }
+                // Descend into hdrl by carrying on with its children.
+            }
+            b"IDIT" => {
+                if size > AVI_IDIT_MAX_SIZE {
+                    anyhow::bail!("AVI: IDIT chunk has implausible size {}", size);
+                }
+                let mut data = vec![0u8; size as usize];
+                reader.read_exact(&mut data)?;
+                let text = String::from_utf8_lossy(&data);
+                let text = text.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+                return parse_avi_date(text);
+            }
+            _ => {
+                reader.seek(SeekFrom::Current(size + (size & 1)))?;
+            }
+        }
+    }
+}
+
+/// IDIT is usually `MON MAR 10 15:04:43 2003`, but some cameras write `2003:03:10 15:04:43`.
+fn parse_avi_date(text: &str) -> Result<DateTime<Utc>> {
+    let normalized = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    for format in ["%a %b %d %H:%M:%S %Y", "%Y:%m:%d %H:%M:%S", "%Y/%m/%d %H:%M:%S", "%Y-%m-%d %H:%M:%S"] {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(&normalized, format) {
+            return Ok(Utc.from_utc_datetime(&naive));
+        }
+    }
+    anyhow::bail!("AVI: Unrecognised IDIT date {:?}", text)
+}
+
+/// Finds the MDPM block in the H.264 SEI of an AVCHD stream. Tag 0x18 holds the
+/// time zone, year and month, tag 0x19 the day and time, all BCD encoded.
+fn avchd_date<R: Read>(reader: &mut R) -> Result<DateTime<Utc>> {
+    let mut data = Vec::new();
+    reader.take(AVCHD_SCAN_LIMIT).read_to_end(&mut data)?;
+    let start = data.windows(MDPM_MARKER.len())
+        .position(|window| window == MDPM_MARKER)
+        .ok_or_else(|| anyhow::anyhow!("AVCHD: No MDPM block found"))?
+        + MDPM_MARKER.len();
+
+    let count = *data.get(start).ok_or_else(|| anyhow::anyhow!("AVCHD: Truncated MDPM block"))? as usize;
+    let mut year_month = None;
+    let mut day_time = None;
+    for entry in data[start + 1..].chunks_exact(5).take(count) {
+        match entry[0] {
+            0x18 => year_month = Some([entry[1], entry[2], entry[3], entry[4]]),
+            0x19 => day_time = Some([entry[1], entry[2], entry[3], entry[4]]),
+            _ => {}
+        }
+    }
+    let (Some([_time_zone, year_hi, year_lo, month]), Some([day, hour, minute, second])) = (year_month, day_time) else {
+        anyhow::bail!("AVCHD: MDPM block has no recording date");
+    };
+
+    let year = bcd(year_hi)? * 100 + bcd(year_lo)?;
+    debug!("AVCHD: MDPM date {:04}-{:02x}-{:02x} {:02x}:{:02x}:{:02x}", year, month, day, hour, minute, second);
+    Utc.with_ymd_and_hms(year as i32, bcd(month)?, bcd(day)?, bcd(hour)?, bcd(minute)?, bcd(second)?)
+        .single()
+        .ok_or_else(|| anyhow::anyhow!("AVCHD: Invalid MDPM date"))
+}
+
+fn bcd(byte: u8) -> Result<u32> {
+    let (high, low) = (byte >> 4, byte & 0x0F);
+    if high > 9 || low > 9 {
+        anyhow::bail!("AVCHD: Invalid BCD value {:#04x}", byte);
+    }
+    Ok((high * 10 + low) as u32)
+}
+
+/// Reads moov > mvhd creation time, seconds since 1904-01-01T00:00:00 UTC.
+fn mvhd_date<R: Read + Seek>(reader: &mut R) -> Result<DateTime<Utc>> {
+    let moov_end = find_box(reader, b"moov", None).context("3GP: No moov box found")?;
+    find_box(reader, b"mvhd", Some(moov_end)).context("3GP: No mvhd box found")?;
+
+    let mut version_flags = [0u8; 4];
+    reader.read_exact(&mut version_flags)?;
+    let seconds = if version_flags[0] == 1 {
+        let mut buf = [0u8; 8];
+        reader.read_exact(&mut buf)?;
+        u64::from_be_bytes(buf)
+    } else {
+        let mut buf = [0u8; 4];
+        reader.read_exact(&mut buf)?;
+        u32::from_be_bytes(buf) as u64
+    };
+    if seconds == 0 {
+        anyhow::bail!("3GP: mvhd creation time is not set");
+    }
+    let epoch = Utc.with_ymd_and_hms(1904, 1, 1, 0, 0, 0).unwrap();
+    Ok(epoch + chrono::Duration::seconds(i64::try_from(seconds)?))
+}
+
+/// Advances to the payload of the named ISO BMFF box, returning the position where it ends.
+/// Boxes that claim to end past the end of the stream are rejected, and a size of 0
+/// (box runs to the end of the file) ends at the stream length.
+pub fn find_box<R: Read + Seek>(reader: &mut R, name: &[u8; 4], end: Option<u64>) -> Result<u64> {
+    let position = reader.stream_position()?;
+    let stream_len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(position))?;
+    loop {
+        let start = reader.stream_position()?;
+        if end.is_some_and(|end| start >= end) {
+            anyhow::bail!("Box {} not found", String::from_utf8_lossy(name));
+        }
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+        let mut size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        let mut header_len = 8;
+        if size == 1 {
+            let mut large = [0u8; 8];
+            reader.read_exact(&mut large)?;
+            size = u64::from_be_bytes(large);
+            header_len = 16;
+        }
+        if size != 0 && size < header_len {
+            anyhow::bail!("Invalid box size {} for {}", size, String::from_utf8_lossy(&header[4..8]));
+        }
+        let box_end = if size == 0 { Some(stream_len) } else { start.checked_add(size) };
+        let Some(box_end) = box_end.filter(|&box_end| box_end <= stream_len) else {
+            anyhow::bail!("Box {} extends past the end of the file", String::from_utf8_lossy(&header[4..8]));
+        };
+        if &header[4..8] == name {
+            return Ok(box_end);
+        }
+        if size == 0 {
+            anyhow::bail!("Box {} not found", String::from_utf8_lossy(name));
+        }
+        reader.seek(SeekFrom::Start(box_end))?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn date(y: i32, mo: u32, d: u32, h: u32, mi: u32, s: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, mo, d, h, mi, s).unwrap()
+    }
+
+    /// An ISO BMFF box with a 32-bit size.
+    fn iso_box(name: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut data = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(name);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn riff_chunk(id: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut data = id.to_vec();
+        data.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        data.extend_from_slice(payload);
+        if payload.len() % 2 == 1 {
+            data.push(0);
+        }
+        data
+    }
+
+    fn avi(children: &[u8]) -> Vec<u8> {
+        let mut hdrl = b"hdrl".to_vec();
+        hdrl.extend_from_slice(children);
+        let mut body = b"AVI ".to_vec();
+        body.extend_from_slice(&riff_chunk(b"LIST", &hdrl));
+        riff_chunk(b"RIFF", &body)
+    }
+
+    #[test]
+    fn read_vint_handles_markers_and_lengths() {
+        assert_eq!(read_vint(&mut Cursor::new([0x81]), false).unwrap(), (1, false));
+        assert_eq!(read_vint(&mut Cursor::new([0x40, 0x02]), false).unwrap(), (2, false));
+        assert_eq!(read_vint(&mut Cursor::new([0x1A, 0x45, 0xDF, 0xA3]), true).unwrap(), (0x1A45_DFA3, false));
+        assert_eq!(read_vint(&mut Cursor::new([0xFF]), false).unwrap(), (0x7F, true));
+        assert!(read_vint(&mut Cursor::new([0x00]), false).is_err());
+        assert!(read_vint(&mut Cursor::new([0x40]), false).is_err());
+    }
+
+    #[test]
+    fn read_ebml_element_reports_unknown_size() {
+        let data = [0x18, 0x53, 0x80, 0x67, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+        assert_eq!(read_ebml_element(&mut Cursor::new(data)).unwrap(), (MATROSKA_SEGMENT, None));
+    }
+
+    #[test]
+    fn matroska_date_reads_date_utc() {
+        let mut data = vec![0x1A, 0x45, 0xDF, 0xA3, 0x80];
+        data.extend_from_slice(&[0x18, 0x53, 0x80, 0x67, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        data.extend_from_slice(&[0x15, 0x49, 0xA9, 0x66, 0x8B, 0x44, 0x61, 0x88]);
+        data.extend_from_slice(&86_400_000_000_000i64.to_be_bytes());
+        assert_eq!(matroska_date(&mut Cursor::new(data)).unwrap(), date(2001, 1, 2, 0, 0, 0));
+    }
+
+    #[test]
+    fn matroska_date_rejects_oversized_elements() {
+        let mut data = vec![0x1A, 0x45, 0xDF, 0xA3, 0x80];
+        data.extend_from_slice(&[0xEC, 0x01, 0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert!(matroska_date(&mut Cursor::new(data)).is_err());
+        assert!(matroska_date(&mut Cursor::new([0x1A, 0x45])).is_err());
+    }
+
+    #[test]
+    fn avi_date_finds_idit_in_hdrl() {
+        let mut children = riff_chunk(b"avih", &[0; 4]);
+        children.extend_from_slice(&riff_chunk(b"IDIT", b"MON MAR 10 15:04:43 2003\n\0"));
+        assert_eq!(avi_date(&mut Cursor::new(avi(&children))).unwrap(), date(2003, 3, 10, 15, 4, 43));
+
+        let children = riff_chunk(b"IDIT", b"2003:03:10 15:04:43");
+        assert_eq!(avi_date(&mut Cursor::new(avi(&children))).unwrap(), date(2003, 3, 10, 15, 4, 43));
+    }
+
+    #[test]
+    fn avi_date_rejects_oversized_and_truncated_chunks() {
+        let mut children = b"IDIT".to_vec();
+        children.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert!(avi_date(&mut Cursor::new(avi(&children))).is_err());
+
+        let mut data = avi(&riff_chunk(b"IDIT", b"MON MAR 10 15:04:43 2003"));
+        data.truncate(data.len() - 10);
+        assert!(avi_date(&mut Cursor::new(data)).is_err());
+
+        let mut data = b"RIFF\0\0\0\0AVI LIST".to_vec();
+        data.extend_from_slice(&2u32.to_le_bytes());
+        assert!(avi_date(&mut Cursor::new(data)).is_err());
+    }
+
+    #[test]
+    fn avi_date_stops_at_movie_data() {
+        let mut body = b"AVI ".to_vec();
+        body.extend_from_slice(&riff_chunk(b"LIST", b"movi"));
+        assert!(avi_date(&mut Cursor::new(riff_chunk(b"RIFF", &body))).is_err());
+    }
+
+    #[test]
+    fn bcd_decodes_and_rejects_invalid_digits() {
+        assert_eq!(bcd(0x42).unwrap(), 42);
+        assert_eq!(bcd(0x09).unwrap(), 9);
+        assert!(bcd(0x4A).is_err());
+        assert!(bcd(0xA1).is_err());
+    }
+
+    #[test]
+    fn avchd_date_reads_mdpm_block() {
+        let mut data = vec![0x47; 100];
+        data.extend_from_slice(MDPM_MARKER);
+        data.extend_from_slice(&[3, 0x18, 0x00, 0x20, 0x24, 0x05, 0x13, 0, 0, 0, 0, 0x19, 0x17, 0x13, 0x45, 0x30]);
+        assert_eq!(avchd_date(&mut Cursor::new(data)).unwrap(), date(2024, 5, 17, 13, 45, 30));
+    }
+
+    #[test]
+    fn avchd_date_rejects_truncated_and_invalid_blocks() {
+        assert!(avchd_date(&mut Cursor::new(MDPM_MARKER.to_vec())).is_err());
+
+        let mut data = MDPM_MARKER.to_vec();
+        data.extend_from_slice(&[2, 0x18, 0x00, 0x20, 0x24, 0x05, 0x19, 0x17]);
+        assert!(avchd_date(&mut Cursor::new(data)).is_err());
+
+        let mut data = MDPM_MARKER.to_vec();
+        data.extend_from_slice(&[2, 0x18, 0x00, 0x20, 0x24, 0x13, 0x19, 0x17, 0x13, 0x45, 0x30]);
+        assert!(avchd_date(&mut Cursor::new(data)).is_err());
+    }
+
+    #[test]
+    fn mvhd_date_reads_creation_time() {
+        let seconds = (date(2024, 5, 17, 13, 45, 30) - date(1904, 1, 1, 0, 0, 0)).num_seconds() as u32;
+        let mut mvhd = vec![0, 0, 0, 0];
+        mvhd.extend_from_slice(&seconds.to_be_bytes());
+        let mut data = iso_box(b"ftyp", b"3gp5\0\0\0\0");
+        data.extend_from_slice(&iso_box(b"moov", &iso_box(b"mvhd", &mvhd)));
+        assert_eq!(mvhd_date(&mut Cursor::new(data)).unwrap(), date(2024, 5, 17, 13, 45, 30));
+    }
+
+    #[test]
+    fn mvhd_date_rejects_unset_time() {
+        let data = iso_box(b"moov", &iso_box(b"mvhd", &[0; 8]));
+        assert!(mvhd_date(&mut Cursor::new(data)).is_err());
+    }
+
+    #[test]
+    fn find_box_returns_payload_end() {
+        let mut data = iso_box(b"free", &[0; 4]);
+        data.extend_from_slice(&iso_box(b"moov", &[1, 2, 3]));
+        let mut reader = Cursor::new(data);
+        assert_eq!(find_box(&mut reader, b"moov", None).unwrap(), 23);
+        assert_eq!(reader.position(), 20);
+    }
+
+    #[test]
+    fn find_box_treats_size_zero_as_end_of_file() {
+        let mut data = vec![0, 0, 0, 0];
+        data.extend_from_slice(b"mdat");
+        data.extend_from_slice(&[0; 20]);
+        assert_eq!(find_box(&mut Cursor::new(data.clone()), b"mdat", None).unwrap(), 28);
+        assert!(find_box(&mut Cursor::new(data), b"moov", None).is_err());
+    }
+
+    #[test]
+    fn find_box_rejects_invalid_sizes() {
+        let mut past_end = 100u32.to_be_bytes().to_vec();
+        past_end.extend_from_slice(b"moov");
+        assert!(find_box(&mut Cursor::new(past_end), b"moov", None).is_err());
+
+        let mut large = 1u32.to_be_bytes().to_vec();
+        large.extend_from_slice(b"moov");
+        large.extend_from_slice(&u64::MAX.to_be_bytes());
+        assert!(find_box(&mut Cursor::new(large), b"moov", None).is_err());
+
+        let mut too_small = 4u32.to_be_bytes().to_vec();
+        too_small.extend_from_slice(b"moov");
+        assert!(find_box(&mut Cursor::new(too_small), b"moov", None).is_err());
+
+        assert!(find_box(&mut Cursor::new(vec![0, 0, 0]), b"moov", None).is_err());
+    }
+
+    #[test]
+    fn find_box_stops_at_parent_end() {
+        let mut data = iso_box(b"moov", &iso_box(b"trak", &[0; 4]));
+        data.extend_from_slice(&iso_box(b"mvhd", &[0; 4]));
+        let mut reader = Cursor::new(data);
+        let moov_end = find_box(&mut reader, b"moov", None).unwrap();
+        assert!(find_box(&mut reader, b"mvhd", Some(moov_end)).is_err());
+    }
+}
@@ -0,0 +1,127 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use log::{debug, warn};
+use regex::Regex;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+/// How much of a media file is searched for an embedded XMP packet.
+const EMBEDDED_XMP_SCAN_LIMIT: u64 = 512 * 1024;
+
+/// XMP properties holding the capture date, in order of preference. Both the
+/// attribute (`exif:DateTimeOriginal="..."`) and element forms are matched.
+static XMP_DATE: LazyLock<Vec<Regex>> = LazyLock::new(|| {
+    ["exif:DateTimeOriginal", "photoshop:DateCreated", "xmp:CreateDate"]
+        .iter()
+        .map(|property| {
+            Regex::new(&format!(r#"{0}\s*=\s*"([^"]+)"|<{0}>([^<]+)</{0}>"#, property)).expect("XMP date pattern is valid")
+        })
+        .collect()
+});
+
+static XMP_TIMESTAMP: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(\d{4})-(\d{2})-(\d{2})(?:T(\d{2}):(\d{2})(?::(\d{2}))?)?").expect("XMP timestamp pattern is valid")
+});
+
+/// Reads the capture date from an XMP sidecar (`IMG_1234.xmp` or `IMG_1234.JPG.xmp`),
+/// or from an XMP packet embedded near the start of the file.
+pub fn extract_xmp_date(file_path: &Path) -> Result<DateTime<Utc>> {
+    for sidecar in sidecar_paths(file_path) {
+        if sidecar.is_file() {
+            match fs::read_to_string(&sidecar) {
+                Ok(content) => {
+                    if let Some(date_time) = find_date(&content) {
+                        debug!("XMP: Found date {} in sidecar {}", date_time, sidecar.display());
+                        return Ok(date_time);
+                    }
+                }
+                Err(e) => warn!("XMP: Failed to read sidecar {}: {}. Trying embedded XMP.", sidecar.display(), e),
+            }
+        }
+    }
+
+    let mut head = Vec::new();
+    File::open(file_path)
+        .with_context(|| format!("XMP: Failed to open file {}", file_path.display()))?
+        .take(EMBEDDED_XMP_SCAN_LIMIT)
+        .read_to_end(&mut head)?;
+    let content = String::from_utf8_lossy(&head);
+    if let Some(start) = content.find("<x:xmpmeta")
+        && let Some(date_time) = find_date(&content[start..])
+    {
+        debug!("XMP: Found embedded date {} in {}", date_time, file_path.display());
+        return Ok(date_time);
+    }
+    anyhow::bail!("XMP: No date found in sidecar or embedded XMP for {}", file_path.display())
+}
+
+fn sidecar_paths(file_path: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![file_path.with_extension("xmp"), file_path.with_extension("XMP")];
+    if let Some(name) = file_path.file_name() {
+        let mut with_suffix = name.to_os_string();
+        with_suffix.push(".xmp");
+        paths.push(file_path.with_file_name(with_suffix));
+    }
+    paths
+}
+
+pub fn is_sidecar(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("xmp"))
+}
+
+/// The existing sidecars of a media file, each paired with its name next to
+/// `destination`: `IMG_1234.xmp` follows the media's new stem and
+/// `IMG_1234.JPG.xmp` its new file name.
+pub fn sidecars_for(file_path: &Path, destination: &Path) -> Vec<(PathBuf, PathBuf)> {
+    let mut found: Vec<(PathBuf, PathBuf)> = Vec::new();
+    for sidecar in sidecar_paths(file_path) {
+        // On case-insensitive filesystems `.xmp` and `.XMP` name the same file.
+        let canonical = fs::canonicalize(&sidecar).ok();
+        if canonical.is_none() || found.iter().any(|(other, _)| fs::canonicalize(other).ok() == canonical) {
+            continue;
+        }
+        let target = if sidecar.with_extension("") == file_path.with_extension("") {
+            destination.with_extension(sidecar.extension().unwrap_or_default())
+        } else {
+            let mut name = destination.file_name().unwrap_or_default().to_os_string();
+            name.push(".xmp");
+            destination.with_file_name(name)
+        };
+        found.push((sidecar, target));
+    }
+    found
+}
+
+/// Files next to a sidecar that it may belong to: `IMG_1234.JPG` for
+/// `IMG_1234.JPG.xmp`, or any `IMG_1234.*` for `IMG_1234.xmp`.
+pub fn media_candidates(sidecar: &Path) -> Vec<PathBuf> {
+    let stem = sidecar.with_extension("");
+    if stem.extension().is_some() && stem.is_file() {
+        return vec![stem];
+    }
+    let (Some(dir), Some(stem)) = (sidecar.parent(), stem.file_name()) else {
+        return Vec::new();
+    };
+    let Ok(entries) = fs::read_dir(if dir.as_os_str().is_empty() { Path::new(".") } else { dir }) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| sidecar.with_file_name(entry.file_name()))
+        .filter(|path| !is_sidecar(path) && path.file_stem() == Some(stem) && path.is_file())
+        .collect()
+}
+
+/// XMP dates are ISO 8601 and may carry a time zone. The local wall-clock time is
+/// used, matching how EXIF dates are handled.
+fn find_date(content: &str) -> Option<DateTime<Utc>> {
+    XMP_DATE.iter().find_map(|regex| {
+        let captures = regex.captures(content)?;
+        let value = captures.get(1).or_else(|| captures.get(2))?.as_str().trim();
+        let parts = XMP_TIMESTAMP.captures(value)?;
+        let number = |index: usize| parts.get(index).map_or(Some(0), |m| m.as_str().parse::<u32>().ok());
+        Utc.with_ymd_and_hms(number(1)? as i32, number(2)?, number(3)?, number(4)?, number(5)?, number(6)?).single()
+    })
+}